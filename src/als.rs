@@ -0,0 +1,366 @@
+use crate::backend::read_int;
+use crate::daemon::Tasks;
+use crate::discovery::class_entries;
+use crate::error::{Result, ScreenpadError};
+use crate::level::Level;
+use crate::screenpad::Screenpad;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Settings for driving the brightness from the ambient light sensor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AlsConfig {
+    /// Adjust inside `screenpadctl daemon`
+    pub enabled: bool,
+    /// IIO device, e.g. `iio:device0` or a path. Detected when unset
+    pub sensor: Option<String>,
+    /// `[lux, percent]` pairs, interpolated linearly in between
+    pub points: Vec<(f64, f64)>,
+    /// Percentage points the target has to move before the brightness does
+    pub hysteresis: f64,
+    /// Weight of a new reading in [0->1], lower is smoother
+    pub smoothing: f64,
+    pub poll_ms: u64,
+}
+
+impl Default for AlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sensor: None,
+            points: vec![(0.0, 10.0), (50.0, 30.0), (300.0, 60.0), (1000.0, 100.0)],
+            hysteresis: 5.0,
+            smoothing: 0.3,
+            poll_ms: 1000,
+        }
+    }
+}
+
+/// Illuminance channel of an IIO light sensor
+pub struct Sensor {
+    dir: PathBuf,
+}
+
+impl Sensor {
+    /// Find the first device under `<sysfs_root>/bus/iio/devices` with an
+    /// illuminance channel, or the one named `name`
+    pub fn find(sysfs_root: &Path, name: Option<&str>) -> Result<Self> {
+        let devices = sysfs_root.join("bus").join("iio").join("devices");
+
+        let dir = match name {
+            Some(name) if name.contains('/') => Some(PathBuf::from(name)),
+            Some(name) => Some(devices.join(name)),
+            None => class_entries(&devices)
+                .into_iter()
+                .find(|dir| Self::channel(dir).is_some()),
+        };
+
+        match dir {
+            Some(dir) if Self::channel(&dir).is_some() => Ok(Self { dir }),
+            _ => Err(ScreenpadError::MissingDevice(format!(
+                "No ambient light sensor found in {}",
+                devices.display()
+            ))),
+        }
+    }
+
+    /// The attribute holding the reading, processed or raw
+    fn channel(dir: &Path) -> Option<PathBuf> {
+        ["in_illuminance_input", "in_illuminance_raw"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.exists())
+    }
+
+    fn optional(&self, name: &str, default: f64) -> Result<f64> {
+        let path = self.dir.join(name);
+        if path.exists() {
+            read_int(&path)
+        } else {
+            Ok(default)
+        }
+    }
+
+    /// Current illuminance in lux
+    pub fn lux(&self) -> Result<f64> {
+        let channel = Self::channel(&self.dir).ok_or_else(|| {
+            ScreenpadError::MissingDevice(format!("{} has no illuminance", self.dir.display()))
+        })?;
+        let value: f64 = read_int(&channel)?;
+
+        if channel.ends_with("in_illuminance_input") {
+            return Ok(value);
+        }
+        // the IIO ABI gives lux as (raw + offset) * scale
+        let offset = self.optional("in_illuminance_offset", 0.0)?;
+        let scale = self.optional("in_illuminance_scale", 1.0)?;
+        Ok((value + offset) * scale)
+    }
+}
+
+/// Turns lux readings into brightness percentages
+pub struct AutoBrightness {
+    points: Vec<(f64, f64)>,
+    hysteresis: f64,
+    smoothing: f64,
+    smoothed: Option<f64>,
+    applied: Option<f64>,
+}
+
+impl AutoBrightness {
+    pub fn new(cfg: &AlsConfig) -> Self {
+        let mut points = cfg.points.clone();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self {
+            points,
+            hysteresis: cfg.hysteresis.max(0.0),
+            smoothing: cfg.smoothing.clamp(0.0, 1.0),
+            smoothed: None,
+            applied: None,
+        }
+    }
+
+    /// Percentage for `lux` on the configured points
+    pub fn percent(&self, lux: f64) -> f64 {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return 100.0;
+        };
+        if lux <= first.0 {
+            return first.1;
+        }
+        if lux >= last.0 {
+            return last.1;
+        }
+
+        let upper = self
+            .points
+            .iter()
+            .position(|point| point.0 >= lux)
+            .unwrap_or(self.points.len() - 1);
+        let (x0, y0) = self.points[upper - 1];
+        let (x1, y1) = self.points[upper];
+        y0 + (y1 - y0) * (lux - x0) / (x1 - x0)
+    }
+
+    /// Feed a reading. Returns the percentage to switch to, or `None` while
+    /// the target stays within the hysteresis
+    pub fn update(&mut self, lux: f64) -> Option<f64> {
+        let smoothed = match self.smoothed {
+            Some(previous) => previous + (lux - previous) * self.smoothing,
+            None => lux,
+        };
+        self.smoothed = Some(smoothed);
+
+        let target = self.percent(smoothed).clamp(0.0, 100.0);
+        match self.applied {
+            Some(applied) if (target - applied).abs() <= self.hysteresis => None,
+            _ => {
+                self.applied = Some(target);
+                Some(target)
+            }
+        }
+    }
+}
+
+/// Set `percent` unless the screenpad is off or dimmed. Stays above the dim
+/// threshold, so a dark room neither dims nor turns off the screenpad.
+/// Returns the new brightness
+pub fn apply(screenpad: &Screenpad, percent: f64) -> Result<Option<i16>> {
+    let max = screenpad.max_brightness()?;
+    let target =
+        Level::Fraction(percent.clamp(0.0, 100.0) / 100.0).resolve(0, max, &screenpad.curve())?;
+    screenpad.adjust(target)
+}
+
+/// Adjust until an error occurs
+pub fn run(screenpad: &Screenpad, sensor: &Sensor, cfg: &AlsConfig) -> Result<()> {
+    let mut auto = AutoBrightness::new(cfg);
+    loop {
+        if let Some(percent) = auto.update(sensor.lux()?) {
+            apply(screenpad, percent)?;
+        }
+        thread::sleep(Duration::from_millis(cfg.poll_ms.max(1)));
+    }
+}
+
+/// Adjust in the background of the daemon
+pub fn spawn(tasks: &Tasks, sensor: Sensor, cfg: &AlsConfig) {
+    let tasks = tasks.clone();
+    let mut auto = AutoBrightness::new(cfg);
+    let poll = Duration::from_millis(cfg.poll_ms.max(1));
+
+    thread::spawn(move || loop {
+        match sensor.lux() {
+            Ok(lux) => {
+                if let Some(percent) = auto.update(lux) {
+                    let submitted = tasks.submit(move |screenpad, _| {
+                        if let Err(err) = apply(screenpad, percent) {
+                            eprintln!("als: {}", err);
+                        }
+                    });
+                    if !submitted {
+                        return;
+                    }
+                }
+            }
+            Err(err) => eprintln!("als: {}", err),
+        }
+        thread::sleep(poll);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+
+    #[test]
+    fn finds_the_first_device_with_illuminance() {
+        let dir = TempDir::new();
+        dir.write("bus/iio/devices/iio:device0/in_accel_x_raw", "12\n");
+        dir.write("bus/iio/devices/iio:device1/in_illuminance_input", "250\n");
+
+        let sensor = Sensor::find(dir.path(), None).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 250.0);
+    }
+
+    #[test]
+    fn finds_a_sensor_by_name_or_path() {
+        let dir = TempDir::new();
+        dir.write("bus/iio/devices/iio:device0/in_illuminance_input", "10\n");
+        let path = dir.write("bus/iio/devices/iio:device1/in_illuminance_input", "20\n");
+
+        let sensor = Sensor::find(dir.path(), Some("iio:device1")).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 20.0);
+        let parent = path.parent().unwrap().to_str().unwrap();
+        assert_eq!(
+            Sensor::find(dir.path(), Some(parent))
+                .unwrap()
+                .lux()
+                .unwrap(),
+            20.0
+        );
+    }
+
+    #[test]
+    fn missing_sensors_are_missing_devices() {
+        let dir = TempDir::new();
+        assert_eq!(Sensor::find(dir.path(), None).err().unwrap().exit_code(), 3);
+
+        dir.write("bus/iio/devices/iio:device0/in_accel_x_raw", "12\n");
+        assert_eq!(Sensor::find(dir.path(), None).err().unwrap().exit_code(), 3);
+        let named = Sensor::find(dir.path(), Some("iio:device0"));
+        assert_eq!(named.err().unwrap().exit_code(), 3);
+    }
+
+    #[test]
+    fn raw_readings_take_offset_and_scale() {
+        let dir = TempDir::new();
+        dir.write("bus/iio/devices/iio:device0/in_illuminance_raw", "100\n");
+        let sensor = Sensor::find(dir.path(), None).unwrap();
+        assert_eq!(sensor.lux().unwrap(), 100.0);
+
+        dir.write("bus/iio/devices/iio:device0/in_illuminance_offset", "10\n");
+        dir.write(
+            "bus/iio/devices/iio:device0/in_illuminance_scale",
+            "0.500000\n",
+        );
+        assert_eq!(sensor.lux().unwrap(), 55.0);
+    }
+
+    #[test]
+    fn percent_interpolates_between_points() {
+        let auto = AutoBrightness::new(&AlsConfig::default());
+
+        assert_eq!(auto.percent(-5.0), 10.0);
+        assert_eq!(auto.percent(0.0), 10.0);
+        assert_eq!(auto.percent(25.0), 20.0);
+        assert_eq!(auto.percent(50.0), 30.0);
+        assert_eq!(auto.percent(175.0), 45.0);
+        assert_eq!(auto.percent(5000.0), 100.0);
+    }
+
+    #[test]
+    fn points_are_sorted_and_may_be_empty() {
+        let cfg = AlsConfig {
+            points: vec![(100.0, 80.0), (0.0, 20.0)],
+            ..AlsConfig::default()
+        };
+        assert_eq!(AutoBrightness::new(&cfg).percent(50.0), 50.0);
+
+        let cfg = AlsConfig {
+            points: Vec::new(),
+            ..AlsConfig::default()
+        };
+        assert_eq!(AutoBrightness::new(&cfg).percent(50.0), 100.0);
+    }
+
+    #[test]
+    fn hysteresis_holds_small_moves_back() {
+        let cfg = AlsConfig {
+            points: vec![(0.0, 0.0), (100.0, 100.0)],
+            hysteresis: 5.0,
+            smoothing: 1.0,
+            ..AlsConfig::default()
+        };
+        let mut auto = AutoBrightness::new(&cfg);
+
+        assert_eq!(auto.update(50.0), Some(50.0));
+        assert_eq!(auto.update(54.0), None);
+        assert_eq!(auto.update(46.0), None);
+        assert_eq!(auto.update(60.0), Some(60.0));
+    }
+
+    #[test]
+    fn smoothing_eases_into_new_readings() {
+        let cfg = AlsConfig {
+            points: vec![(0.0, 0.0), (100.0, 100.0)],
+            hysteresis: 0.0,
+            smoothing: 0.5,
+            ..AlsConfig::default()
+        };
+        let mut auto = AutoBrightness::new(&cfg);
+
+        assert_eq!(auto.update(0.0), Some(0.0));
+        assert_eq!(auto.update(100.0), Some(50.0));
+        assert_eq!(auto.update(100.0), Some(75.0));
+    }
+
+    #[test]
+    fn apply_sets_the_percentage() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        assert_eq!(apply(&screenpad, 50.0).unwrap(), Some(128));
+        assert_eq!(apply(&screenpad, 50.0).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 128);
+    }
+
+    #[test]
+    fn apply_never_dims_or_turns_off() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        // 1 reads as dimmed, the screen must keep reading as on
+        assert_eq!(apply(&screenpad, 0.0).unwrap(), Some(2));
+        assert_eq!(screenpad.get_brightness().unwrap(), 2);
+    }
+
+    #[test]
+    fn apply_leaves_a_dimmed_or_off_screen_alone() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        screenpad.dim().unwrap();
+        assert_eq!(apply(&screenpad, 80.0).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 1);
+
+        screenpad.off().unwrap();
+        assert_eq!(apply(&screenpad, 80.0).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::error::{Result, ScreenpadError};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI16, Ordering};
+
+/// A device the screenpad brightness can be read from and written to
+pub trait ScreenpadBackend: Send + Sync {
+    /// Current raw brightness
+    fn read(&self) -> Result<i16>;
+
+    /// Overwrite raw brightness
+    fn write(&self, value: i16) -> Result<()>;
+
+    /// Highest raw brightness the device accepts
+    fn max(&self) -> Result<i16>;
+
+    /// Attribute the kernel notifies through sysfs poll when the brightness
+    /// changes, if any
+    fn watch_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Directory whose files change when the brightness is written
+    fn watch_dir(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Read a single integer from a sysfs attribute
+pub(crate) fn read_int<T: FromStr>(path: &Path) -> Result<T> {
+    let mut value = fs::read_to_string(path).map_err(|err| ScreenpadError::device(path, err))?;
+
+    if value.ends_with('\n') {
+        value.pop();
+    }
+
+    value.parse::<T>().map_err(|_| ScreenpadError::Parse {
+        path: path.to_path_buf(),
+        value,
+    })
+}
+
+/// Write a single integer to a sysfs attribute
+pub(crate) fn write_int(path: &Path, value: i16) -> Result<()> {
+    fs::write(path, value.to_string()).map_err(|err| ScreenpadError::device(path, err))
+}
+
+/// LED class device, as created by the patched asus-wmi module
+pub struct SysfsLed {
+    dir: PathBuf,
+}
+
+impl SysfsLed {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl ScreenpadBackend for SysfsLed {
+    fn read(&self) -> Result<i16> {
+        read_int(&self.dir.join("brightness"))
+    }
+
+    fn write(&self, value: i16) -> Result<()> {
+        write_int(&self.dir.join("brightness"), value)
+    }
+
+    fn max(&self) -> Result<i16> {
+        read_int(&self.dir.join("max_brightness"))
+    }
+
+    fn watch_path(&self) -> Option<PathBuf> {
+        // only changes by the hardware, such as hotkeys, are notified
+        Some(self.dir.join("brightness_hw_changed")).filter(|path| path.exists())
+    }
+
+    fn watch_dir(&self) -> Option<PathBuf> {
+        Some(self.dir.clone())
+    }
+}
+
+/// Backlight class device, as exposed by mainline kernels
+pub struct SysfsBacklight {
+    dir: PathBuf,
+}
+
+impl SysfsBacklight {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl ScreenpadBackend for SysfsBacklight {
+    fn read(&self) -> Result<i16> {
+        // actual_brightness is what the hardware reports, brightness is only
+        // the last requested value
+        let actual_brightness = self.dir.join("actual_brightness");
+        if actual_brightness.exists() {
+            read_int(&actual_brightness)
+        } else {
+            read_int(&self.dir.join("brightness"))
+        }
+    }
+
+    fn write(&self, value: i16) -> Result<()> {
+        write_int(&self.dir.join("brightness"), value)
+    }
+
+    fn max(&self) -> Result<i16> {
+        read_int(&self.dir.join("max_brightness"))
+    }
+
+    fn watch_path(&self) -> Option<PathBuf> {
+        Some(self.dir.join("actual_brightness")).filter(|path| path.exists())
+    }
+
+    fn watch_dir(&self) -> Option<PathBuf> {
+        Some(self.dir.clone())
+    }
+}
+
+/// In-memory device, for running without the hardware
+pub struct MockBackend {
+    brightness: AtomicI16,
+    max: i16,
+}
+
+impl MockBackend {
+    pub fn new(brightness: i16, max: i16) -> Self {
+        Self {
+            brightness: AtomicI16::new(brightness),
+            max,
+        }
+    }
+}
+
+impl ScreenpadBackend for MockBackend {
+    fn read(&self) -> Result<i16> {
+        Ok(self.brightness.load(Ordering::SeqCst))
+    }
+
+    fn write(&self, value: i16) -> Result<()> {
+        if !(0..=self.max).contains(&value) {
+            return Err(ScreenpadError::OutOfRange {
+                value: value.into(),
+                max: self.max,
+            });
+        }
+        self.brightness.store(value, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn max(&self) -> Result<i16> {
+        Ok(self.max)
+    }
+}
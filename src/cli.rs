@@ -0,0 +1,165 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use screenpadctl::discovery::DEFAULT_SYSFS_ROOT;
+use screenpadctl::Level;
+use std::path::PathBuf;
+
+const EXIT_CODES: &str = "Exit codes:
+  0 success, 1 I/O error, 2 usage error, 3 missing device,
+  4 permission denied, 5 parse failure, 6 missing backup,
+  7 config error, 8 brightness out of range, 9 D-Bus error";
+
+/// Command line tool to control the screenpad on asus zenbook duo devices
+#[derive(Parser)]
+#[command(name = "screenpadctl", version, after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Use this device (name or path) instead of auto-detecting
+    #[arg(long, global = true, value_name = "NAME|PATH")]
+    pub device: Option<String>,
+
+    /// Control this LED, e.g. asus::kbd_backlight, instead of the screenpad
+    #[arg(long, global = true, value_name = "NAME", conflicts_with = "device")]
+    pub led: Option<String>,
+
+    /// Look for devices under this directory
+    #[arg(
+        long,
+        global = true,
+        env = "SCREENPADCTL_SYSFS_ROOT",
+        default_value = DEFAULT_SYSFS_ROOT,
+        value_name = "PATH"
+    )]
+    pub sysfs_root: PathBuf,
+
+    /// Print nothing on success
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Print results as JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Print current brightness and range
+    #[command(visible_alias = "b")]
+    Get,
+
+    /// Set the brightness manually
+    #[command(visible_alias = "bset")]
+    Set {
+        /// Raw int (120), percentage (40%), fraction (0.5),
+        /// step (+10, -10, +10%, -25%), `max` or `min`
+        #[arg(allow_hyphen_values = true)]
+        value: Level,
+    },
+
+    /// Increase brightness by the configured increment
+    #[command(visible_alias = "bup")]
+    Up,
+
+    /// Decrease brightness by the configured increment
+    #[command(visible_alias = "bdown")]
+    Down,
+
+    /// Turn the screen on at its previous brightness
+    On,
+
+    /// Turn the screen off
+    Off,
+
+    /// Dim the screen
+    Dim,
+
+    /// Toggle between on and off
+    Toggle,
+
+    /// Cycle between [on -> dim -> off] (loops), or the configured cycle
+    Cycle,
+
+    /// Go to a preset from the config, or to `on`, `dim` or `off`
+    Preset { name: String },
+
+    /// Print the state for a status bar
+    Status {
+        #[arg(long, value_enum, default_value_t = StatusFormat::Plain)]
+        format: StatusFormat,
+
+        /// Keep running and print a new line whenever the brightness changes
+        #[arg(long)]
+        follow: bool,
+
+        /// Longest time between two checks with --follow
+        #[arg(long, default_value_t = 1000, value_name = "MS")]
+        interval_ms: u64,
+    },
+
+    /// Print an event on every change of brightness or state until interrupted
+    Watch {
+        /// Longest time between two checks
+        #[arg(long, default_value_t = 1000, value_name = "MS")]
+        interval_ms: u64,
+    },
+
+    /// Own the device and serve other invocations over a Unix socket
+    Daemon,
+
+    /// Mirror the main panel backlight until interrupted
+    Follow,
+
+    /// Adjust to the ambient light sensor until interrupted
+    Auto,
+
+    /// Inspect the time-of-day schedule
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+
+    /// Serve org.screenpadctl.Screenpad on the D-Bus session bus
+    #[cfg(feature = "dbus")]
+    Dbus,
+
+    /// Show the config, or change a brightness increment
+    #[command(visible_alias = "bconfig")]
+    Config {
+        /// Which increment to change
+        #[arg(requires = "value")]
+        increment: Option<Increment>,
+
+        /// New increment value
+        #[arg(allow_negative_numbers = true)]
+        value: Option<i16>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ScheduleCommand {
+    /// Print today's scheduled brightness
+    Preview {
+        /// Minutes between two printed times
+        #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u16).range(1..=1440))]
+        step: u16,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum StatusFormat {
+    /// `on 78% (200/255)`
+    Plain,
+    /// JSON with text, tooltip, class and percentage
+    Waybar,
+    /// Icon and percentage
+    Polybar,
+    /// full_text and short_text lines
+    I3blocks,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Increment {
+    Pos,
+    Neg,
+}
@@ -0,0 +1,455 @@
+use crate::config::Config;
+use crate::error::{Result, ScreenpadError};
+use crate::level::Level;
+use crate::screenpad::{ScreenState, Screenpad};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Something to do with the screenpad, shared by the command line and the
+/// daemon protocol. The text form is the command name with an optional
+/// argument, e.g. `set 40%`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Get,
+    Set(Level),
+    Up,
+    Down,
+    On,
+    Off,
+    Dim,
+    Toggle,
+    Cycle,
+    Preset(String),
+}
+
+impl Action {
+    /// Whether the action writes the device, and so takes over from a fade
+    /// still running. Reads must not interrupt one
+    pub fn writes(&self) -> bool {
+        !matches!(self, Action::Get)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Get => "get",
+            Action::Set(_) => "set",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::On => "on",
+            Action::Off => "off",
+            Action::Dim => "dim",
+            Action::Toggle => "toggle",
+            Action::Cycle => "cycle",
+            Action::Preset(_) => "preset",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Set(level) => write!(f, "set {}", level),
+            Action::Preset(name) => write!(f, "preset {}", name),
+            action => f.write_str(action.name()),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ScreenpadError;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let argument = words.next();
+
+        if words.next().is_some() {
+            return Err(ScreenpadError::Usage(format!(
+                "Too many arguments in `{}`",
+                line
+            )));
+        }
+
+        let action = match (name, argument) {
+            ("set", Some(level)) => return Ok(Action::Set(level.parse()?)),
+            ("preset", Some(name)) => Action::Preset(name.to_string()),
+            ("get", None) => Action::Get,
+            ("up", None) => Action::Up,
+            ("down", None) => Action::Down,
+            ("on", None) => Action::On,
+            ("off", None) => Action::Off,
+            ("dim", None) => Action::Dim,
+            ("toggle", None) => Action::Toggle,
+            ("cycle", None) => Action::Cycle,
+            _ => return Err(ScreenpadError::Usage(format!("Invalid command `{}`", line))),
+        };
+        Ok(action)
+    }
+}
+
+/// Result of an [`Action`] with the device state after it. This is the
+/// `--json` output of the device commands, so fields are only ever added
+///
+/// | field            | type           | meaning                               |
+/// |------------------|----------------|---------------------------------------|
+/// | `action`         | string         | command that ran, e.g. `up`           |
+/// | `changed`        | bool           | whether the brightness was changed    |
+/// | `message`        | string         | what the command line prints          |
+/// | `brightness`     | int            | raw brightness after the command      |
+/// | `max_brightness` | int            | raw maximum of the device             |
+/// | `percentage`     | float          | brightness on the curve, in [0->100]  |
+/// | `state`          | string         | `on`, `dim` or `off`                  |
+/// | `backup`         | int or null    | brightness `on` would restore         |
+/// | `interrupted`    | bool           | a later command cut the fade short    |
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub action: String,
+    /// Whether the brightness was changed
+    pub changed: bool,
+    pub message: String,
+    pub brightness: i16,
+    pub max_brightness: i16,
+    #[serde(default)]
+    pub percentage: f64,
+    pub state: String,
+    #[serde(default)]
+    pub backup: Option<i16>,
+    #[serde(default)]
+    pub interrupted: bool,
+}
+
+impl Screenpad {
+    /// Run `action`, taking increments from `cfg`
+    pub fn execute(&self, action: Action, cfg: &Config) -> Result<Outcome> {
+        let (changed, message) = match &action {
+            Action::Get => (
+                false,
+                format!(
+                    "Current Brightness is {} (max {})",
+                    self.get_brightness()?,
+                    self.max_brightness()?
+                ),
+            ),
+
+            Action::Up => {
+                let change = self.increment_brightness(cfg.positive_increment)?;
+                let message = if change.changed() {
+                    format!("Brightness up to {}", change.to)
+                } else {
+                    format!("Brightness is already at the maximum of {}", change.to)
+                };
+                (change.changed(), message)
+            }
+            Action::Down => {
+                let change = self.increment_brightness(cfg.negative_increment)?;
+                let message = if change.changed() {
+                    format!("Brightness down to {}", change.to)
+                } else {
+                    format!("Brightness is already at the minimum of {}", change.to)
+                };
+                (change.changed(), message)
+            }
+            Action::Set(level) => {
+                let from = self.get_brightness()?;
+                let value = self.set_level(*level)?;
+                (from != value, format!("Set brightness to {}", value))
+            }
+
+            Action::On | Action::Off | Action::Dim => {
+                let from = self.get_brightness()?;
+                let transition = match action {
+                    Action::On => self.on()?,
+                    Action::Off => self.off()?,
+                    _ => self.dim()?,
+                };
+                if transition.interrupted {
+                    return self.interrupted(&action, from, transition.to);
+                }
+                let message = if transition.changed() {
+                    format!("Screen {}", transition.to)
+                } else {
+                    format!("Screen is already {}", transition.to)
+                };
+                (transition.changed(), message)
+            }
+            Action::Toggle => {
+                let from = self.get_brightness()?;
+                let transition = self.toggle()?;
+                if transition.interrupted {
+                    return self.interrupted(&action, from, transition.to);
+                }
+                let message = if transition.changed() {
+                    format!("Toggle screen {}", transition.to)
+                } else {
+                    "Screen is dimmed, toggle only switches between on and off".to_string()
+                };
+                (transition.changed(), message)
+            }
+            Action::Cycle => {
+                // on -> dim -> off unless configured
+                let from = self.get_brightness()?;
+                let step = self.cycle()?;
+                if step.interrupted {
+                    return self.interrupted(&action, from, self.screen_state()?);
+                }
+                (true, format!("Cycle {} -> {}", step.from, step.to))
+            }
+            Action::Preset(name) => {
+                let change = self.preset(name)?;
+                if change.interrupted {
+                    return self.interrupted(&action, change.from, self.screen_state()?);
+                }
+                let message = if change.changed() {
+                    format!("Preset {}, brightness {}", name, change.to)
+                } else {
+                    format!("Already at preset {}, brightness {}", name, change.to)
+                };
+                (change.changed(), message)
+            }
+        };
+
+        self.outcome(action.name(), changed, message)
+    }
+
+    /// Outcome of `action` when a later command cut its fade short at
+    /// `state`, starting from brightness `from`
+    fn interrupted(&self, action: &Action, from: i16, state: ScreenState) -> Result<Outcome> {
+        let changed = self.get_brightness()? != from;
+        let message = format!("Interrupted by a later command, screen {}", state);
+        Ok(Outcome {
+            interrupted: true,
+            ..self.outcome(action.name(), changed, message)?
+        })
+    }
+
+    /// Describe the device state after `action`
+    pub fn outcome(&self, action: &str, changed: bool, message: String) -> Result<Outcome> {
+        let state: ScreenState = self.screen_state()?;
+        let brightness = self.get_brightness()?;
+        let max_brightness = self.max_brightness()?;
+
+        Ok(Outcome {
+            action: action.to_string(),
+            changed,
+            message,
+            brightness,
+            max_brightness,
+            percentage: (self.curve().perceived(brightness, max_brightness) * 1000.0).round()
+                / 10.0,
+            state: state.to_string(),
+            // a broken backup only matters to `on`, which reports it
+            backup: self.stored_brightness().unwrap_or(None),
+            interrupted: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fade::{Easing, Fade, MockClock};
+    use crate::testutil::{self, TempDir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn run(screenpad: &Screenpad, action: Action) -> Outcome {
+        screenpad
+            .execute(action, &Config::default())
+            .expect("action runs")
+    }
+
+    #[test]
+    fn get_reports_without_changing() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 120, 255);
+
+        let outcome = run(&screenpad, Action::Get);
+        assert!(!outcome.changed);
+        assert_eq!(outcome.brightness, 120);
+        assert_eq!(outcome.max_brightness, 255);
+        assert_eq!(outcome.state, "on");
+        assert_eq!(outcome.backup, None);
+    }
+
+    #[test]
+    fn set_takes_raw_values_and_percentages() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 120, 200);
+
+        assert_eq!(run(&screenpad, Action::Set(Level::Raw(80))).brightness, 80);
+        assert_eq!(
+            run(&screenpad, Action::Set(Level::Fraction(0.5))).brightness,
+            100
+        );
+        assert!(!run(&screenpad, Action::Set(Level::Raw(100))).changed);
+
+        let err = screenpad
+            .execute(Action::Set(Level::Raw(201)), &Config::default())
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 8);
+        assert_eq!(screenpad.get_brightness().unwrap(), 100);
+    }
+
+    #[test]
+    fn up_and_down_step_and_saturate() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 245, 255);
+
+        let outcome = run(&screenpad, Action::Up);
+        assert!(outcome.changed);
+        assert_eq!(outcome.brightness, 255);
+        assert!(!run(&screenpad, Action::Up).changed);
+
+        assert_eq!(run(&screenpad, Action::Down).brightness, 240);
+        screenpad.overwrite_brightness(10).unwrap();
+        assert_eq!(run(&screenpad, Action::Down).brightness, 0);
+        assert!(!run(&screenpad, Action::Down).changed);
+    }
+
+    #[test]
+    fn off_and_on_restore_the_backup() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 120, 255);
+
+        let outcome = run(&screenpad, Action::Off);
+        assert!(outcome.changed);
+        assert_eq!(outcome.brightness, 0);
+        assert_eq!(outcome.state, "off");
+        assert_eq!(outcome.backup, Some(120));
+        assert_eq!(dir.read("brightness_backup"), "120");
+        assert!(!run(&screenpad, Action::Off).changed);
+
+        let outcome = run(&screenpad, Action::On);
+        assert!(outcome.changed);
+        assert_eq!(outcome.brightness, 120);
+        assert!(!run(&screenpad, Action::On).changed);
+    }
+
+    #[test]
+    fn on_without_backup_fails() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 0, 255);
+
+        let err = screenpad
+            .execute(Action::On, &Config::default())
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 6);
+    }
+
+    #[test]
+    fn dim_backs_up_and_on_restores() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 90, 255);
+
+        let outcome = run(&screenpad, Action::Dim);
+        assert_eq!(outcome.brightness, 1);
+        assert_eq!(outcome.state, "dim");
+        assert_eq!(outcome.backup, Some(90));
+        assert!(!run(&screenpad, Action::Dim).changed);
+
+        assert_eq!(run(&screenpad, Action::On).brightness, 90);
+    }
+
+    #[test]
+    fn toggle_switches_on_and_off_but_leaves_dim() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 150, 255);
+
+        assert_eq!(run(&screenpad, Action::Toggle).state, "off");
+        let outcome = run(&screenpad, Action::Toggle);
+        assert_eq!(outcome.state, "on");
+        assert_eq!(outcome.brightness, 150);
+
+        run(&screenpad, Action::Dim);
+        let outcome = run(&screenpad, Action::Toggle);
+        assert!(!outcome.changed);
+        assert_eq!(outcome.state, "dim");
+    }
+
+    #[test]
+    fn cycle_loops_through_on_dim_off() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        let states: Vec<String> = (0..4)
+            .map(|_| run(&screenpad, Action::Cycle).state)
+            .collect();
+        assert_eq!(states, ["dim", "off", "on", "dim"]);
+        assert_eq!(dir.read("brightness_backup"), "200");
+
+        screenpad.overwrite_brightness(0).unwrap();
+        let outcome = run(&screenpad, Action::Cycle);
+        assert_eq!(outcome.message, "Cycle off -> on");
+        assert_eq!(outcome.brightness, 200);
+    }
+
+    #[test]
+    fn actions_round_trip_through_text() {
+        for line in [
+            "get", "set 40%", "set +10", "up", "down", "on", "off", "dim", "toggle",
+        ] {
+            assert_eq!(line.parse::<Action>().unwrap().to_string(), line);
+        }
+        assert!("set".parse::<Action>().is_err());
+        assert!("up 3".parse::<Action>().is_err());
+    }
+
+    /// Screenpad fading over a second that a later command interrupts after
+    /// `checks` looks
+    fn interrupted_after(dir: &TempDir, brightness: i16, checks: usize) -> Screenpad {
+        let looks = AtomicUsize::new(0);
+        testutil::screenpad(dir, brightness, 255)
+            .with_fade(Fade {
+                duration_ms: 1000,
+                easing: Easing::Linear,
+            })
+            .with_clock(Box::<MockClock>::default())
+            .with_interrupt(move || looks.fetch_add(1, Ordering::SeqCst) >= checks)
+    }
+
+    #[test]
+    fn an_interrupted_off_reports_the_state_reached() {
+        let dir = TempDir::new();
+        let screenpad = interrupted_after(&dir, 200, 20);
+
+        let outcome = run(&screenpad, Action::Off);
+        assert!(outcome.interrupted);
+        assert!(outcome.changed);
+        assert_eq!(outcome.state, "on");
+        assert!(outcome.brightness > 0 && outcome.brightness < 200);
+        assert!(
+            outcome.message.contains("Interrupted"),
+            "{}",
+            outcome.message
+        );
+    }
+
+    #[test]
+    fn an_interrupt_before_any_write_changes_nothing() {
+        let dir = TempDir::new();
+        let screenpad = interrupted_after(&dir, 200, 0);
+
+        let outcome = run(&screenpad, Action::Dim);
+        assert!(outcome.interrupted);
+        assert!(!outcome.changed);
+        assert_eq!((outcome.brightness, outcome.state.as_str()), (200, "on"));
+    }
+
+    #[test]
+    fn an_interrupted_cycle_and_toggle_say_so() {
+        let dir = TempDir::new();
+        assert!(run(&interrupted_after(&dir, 200, 20), Action::Toggle).interrupted);
+        assert!(run(&interrupted_after(&dir, 200, 20), Action::Cycle).interrupted);
+        assert!(!run(&interrupted_after(&dir, 200, 20), Action::Up).interrupted);
+    }
+
+    #[test]
+    fn only_reads_leave_a_fade_running() {
+        assert!(!Action::Get.writes());
+        for action in [
+            "set 40%", "up", "down", "on", "off", "dim", "toggle", "cycle",
+        ] {
+            assert!(action.parse::<Action>().unwrap().writes(), "{}", action);
+        }
+    }
+}
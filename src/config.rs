@@ -0,0 +1,287 @@
+use crate::als::AlsConfig;
+use crate::curve::Curve;
+use crate::error::{Result, ScreenpadError};
+use crate::fade::Fade;
+use crate::follow::FollowConfig;
+use crate::idle::IdleConfig;
+use crate::level::Level;
+use crate::power::PowerConfig;
+use crate::schedule::ScheduleConfig;
+use crate::touch::TouchConfig;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const APP_NAME: &str = "screenpadctl";
+
+/// Settings of an LED used through `--led`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LedConfig {
+    pub positive_increment: i16,
+    pub negative_increment: i16,
+    /// Where `off` keeps the brightness for `on`. In the state directory
+    /// when unset
+    pub backup_file: Option<PathBuf>,
+    /// Brightness of `dim`
+    pub dim_level: Level,
+    /// Highest brightness that reads as dimmed
+    pub dim_threshold: Level,
+    /// Named brightness levels of this LED
+    pub presets: BTreeMap<String, Level>,
+    /// Preset or state names `cycle` goes through
+    pub cycle: Vec<String>,
+}
+
+impl Default for LedConfig {
+    fn default() -> Self {
+        // LEDs such as the keyboard backlight only have a few levels
+        Self {
+            positive_increment: 1,
+            negative_increment: -1,
+            backup_file: None,
+            dim_level: Level::Raw(1),
+            dim_threshold: Level::Raw(1),
+            presets: BTreeMap::new(),
+            cycle: default_cycle(),
+        }
+    }
+}
+
+/// Icons of `screenpadctl status`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StatusConfig {
+    pub icon_on: String,
+    pub icon_dim: String,
+    pub icon_off: String,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        Self {
+            icon_on: "☀".to_string(),
+            icon_dim: "◐".to_string(),
+            icon_off: "○".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Config {
+    pub positive_increment: i16,
+    pub negative_increment: i16,
+    /// Preset or state names `cycle` goes through
+    pub cycle: Vec<String>,
+    /// Brightness of `dim`, unless a `dim` preset sets it
+    pub dim_level: Level,
+    /// Highest brightness that reads as dimmed, raised to `dim_level`
+    pub dim_threshold: Level,
+    /// Scale that increments and percentages are taken on
+    pub curve: Curve,
+    /// Fade applied to on, off, dim, toggle and cycle
+    pub fade: Fade,
+    /// Mirror the main panel backlight
+    pub follow: FollowConfig,
+    /// Adjust to the ambient light
+    pub als: AlsConfig,
+    /// Dim and turn off without input
+    pub idle: IdleConfig,
+    /// Profiles for AC and battery
+    pub power: PowerConfig,
+    /// Brightness by time of day
+    pub schedule: ScheduleConfig,
+    /// Touchscreen to inhibit while off
+    pub touch: TouchConfig,
+    /// Named brightness levels, e.g. `reading = "40%"`
+    pub presets: BTreeMap<String, Level>,
+    /// Status bar output
+    pub status: StatusConfig,
+    /// Per LED settings, keyed by name such as `asus::kbd_backlight`
+    pub leds: BTreeMap<String, LedConfig>,
+}
+
+impl ::std::default::Default for Config {
+    fn default() -> Self {
+        Self {
+            positive_increment: 15,
+            negative_increment: -15,
+            cycle: default_cycle(),
+            dim_level: Level::Raw(1),
+            dim_threshold: Level::Raw(1),
+            curve: Curve::Linear,
+            fade: Fade::default(),
+            follow: FollowConfig::default(),
+            als: AlsConfig::default(),
+            idle: IdleConfig::default(),
+            power: PowerConfig::default(),
+            schedule: ScheduleConfig::default(),
+            touch: TouchConfig::default(),
+            presets: BTreeMap::new(),
+            status: StatusConfig::default(),
+            leds: BTreeMap::new(),
+        }
+    }
+}
+
+/// on -> dim -> off
+pub fn default_cycle() -> Vec<String> {
+    ["on", "dim", "off"].map(String::from).to_vec()
+}
+
+/// Fails on a dim setting `name` that no device can take: a step, or a
+/// percentage above 100%. Raw values are checked against the device
+fn validate_dim(name: &str, level: Level) -> Result<()> {
+    match level {
+        Level::RawStep(_) | Level::FractionStep(_) => Err(ScreenpadError::InvalidConfig(format!(
+            "`{}` cannot be a step like `{}`",
+            name, level
+        ))),
+        Level::Fraction(fraction) if fraction > 1.0 => Err(ScreenpadError::InvalidConfig(format!(
+            "`{}` cannot be above 100%, not `{}`",
+            name, level
+        ))),
+        _ => Ok(()),
+    }
+}
+
+impl Config {
+    /// Fails on values that parse but cannot work
+    pub fn validate(&self) -> Result<()> {
+        self.curve.validate()?;
+        self.schedule.validate()?;
+        validate_dim("dim_level", self.dim_level)?;
+        validate_dim("dim_threshold", self.dim_threshold)?;
+        if let Some(level) = self.presets.get("dim") {
+            validate_dim("presets.dim", *level)?;
+        }
+        for (name, led) in &self.leds {
+            validate_dim(&format!("leds.\"{}\".dim_level", name), led.dim_level)?;
+            validate_dim(
+                &format!("leds.\"{}\".dim_threshold", name),
+                led.dim_threshold,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Settings of the LED `name`
+    pub fn led(&self, name: &str) -> LedConfig {
+        self.leds.get(name).cloned().unwrap_or_default()
+    }
+
+    /// This config with the settings of the LED `name`. Raw levels of the
+    /// screenpad mean nothing on an LED with another range, so increments,
+    /// dimming, presets and cycle come from the LED alone
+    pub fn for_led(&self, name: &str) -> Config {
+        let led = self.led(name);
+        Config {
+            positive_increment: led.positive_increment,
+            negative_increment: led.negative_increment,
+            dim_level: led.dim_level,
+            dim_threshold: led.dim_threshold,
+            presets: led.presets,
+            cycle: led.cycle,
+            ..self.clone()
+        }
+    }
+}
+
+pub fn load() -> Result<Config> {
+    let cfg: Config = confy::load(APP_NAME, None)?;
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+pub fn store(cfg: &Config) -> Result<()> {
+    Ok(confy::store(APP_NAME, None, cfg)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_a_gamma_exponent_that_is_not_positive() {
+        for exponent in ["0.0", "-1.0", "nan"] {
+            let text = format!("[curve]\nmode = \"gamma\"\nexponent = {}\n", exponent);
+            let cfg: Config = toml::from_str(&text).expect("config parses");
+            assert_eq!(cfg.validate().unwrap_err().exit_code(), 7);
+        }
+
+        let cfg: Config = toml::from_str("[curve]\nmode = \"gamma\"\nexponent = 2.2\n").unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn sun_schedule_requires_a_position() {
+        let cfg: Config = toml::from_str("[schedule]\nmode = \"sun\"\n").unwrap();
+        assert_eq!(cfg.validate().unwrap_err().exit_code(), 7);
+
+        let cfg: Config = toml::from_str("[schedule]\nmode = \"sun\"\nlatitude = 52.52\n").unwrap();
+        assert_eq!(cfg.validate().unwrap_err().exit_code(), 7);
+
+        let text = "[schedule]\nmode = \"sun\"\nlatitude = 52.52\nlongitude = 13.40\n";
+        let cfg: Config = toml::from_str(text).unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_dim_levels_no_device_can_take() {
+        for text in [
+            "dim_level = \"+5\"",
+            "dim_threshold = \"-10%\"",
+            "dim_level = \"150%\"",
+            "[leds.\"asus::kbd_backlight\"]\ndim_threshold = \"+1\"",
+            "[presets]\ndim = \"-5\"",
+        ] {
+            let cfg: Config = toml::from_str(text).expect("config parses");
+            assert_eq!(cfg.validate().unwrap_err().exit_code(), 7, "{}", text);
+        }
+
+        let cfg: Config = toml::from_str("dim_level = \"5%\"\ndim_threshold = \"40\"").unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn leds_keep_their_own_levels() {
+        let text = r#"
+            dim_level = "40"
+            dim_threshold = "20"
+            cycle = ["reading", "off"]
+
+            [presets]
+            reading = "102"
+
+            [leds."asus::kbd_backlight"]
+            dim_threshold = "2"
+
+            [leds."asus::kbd_backlight".presets]
+            low = "1"
+        "#;
+        let cfg: Config = toml::from_str(text).unwrap();
+
+        let led = cfg.for_led("asus::kbd_backlight");
+        assert_eq!(led.dim_level, Level::Raw(1));
+        assert_eq!(led.dim_threshold, Level::Raw(2));
+        assert_eq!(led.cycle, default_cycle());
+        assert_eq!(
+            led.presets,
+            BTreeMap::from([("low".to_string(), Level::Raw(1))])
+        );
+        assert_eq!((led.positive_increment, led.negative_increment), (1, -1));
+
+        let other = cfg.for_led("input3::capslock");
+        assert_eq!(other.dim_threshold, Level::Raw(1));
+        assert!(other.presets.is_empty());
+    }
+
+    #[test]
+    fn default_config_round_trips() {
+        let text = toml::to_string(&Config::default()).expect("config serializes");
+        let cfg: Config = toml::from_str(&text).expect("config parses");
+        assert_eq!(toml::to_string(&cfg).unwrap(), text);
+        assert!(cfg.validate().is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::error::{Result, ScreenpadError};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// Base of the exponential curve, the light output grows this many times
+/// over the perceptual range
+const EXPONENTIAL_BASE: f64 = 100.0;
+
+/// Mapping between perceived brightness and raw device brightness, both as
+/// fractions in [0->1]. Steps and percentages are taken on the perceived
+/// scale, so a fixed number of presses covers the range evenly to the eye
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Curve {
+    #[default]
+    Linear,
+    Exponential,
+    Gamma {
+        exponent: f64,
+    },
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Curve::Linear => f.write_str("linear"),
+            Curve::Exponential => f.write_str("exponential"),
+            Curve::Gamma { exponent } => write!(f, "gamma {}", exponent),
+        }
+    }
+}
+
+impl Curve {
+    /// Fails on a gamma exponent that is not above 0, which would read every
+    /// brightness as 100%
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Curve::Gamma { exponent } if !(exponent > 0.0 && exponent.is_finite()) => {
+                Err(ScreenpadError::InvalidConfig(format!(
+                    "The gamma exponent has to be above 0, not {}",
+                    exponent
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Raw fraction for a perceived fraction
+    pub fn to_raw(&self, perceived: f64) -> f64 {
+        let perceived = perceived.clamp(0.0, 1.0);
+
+        match *self {
+            Curve::Linear => perceived,
+            Curve::Exponential => {
+                (EXPONENTIAL_BASE.powf(perceived) - 1.0) / (EXPONENTIAL_BASE - 1.0)
+            }
+            Curve::Gamma { exponent } => perceived.powf(exponent.max(f64::EPSILON)),
+        }
+    }
+
+    /// Perceived fraction for a raw fraction, the inverse of `to_raw`
+    pub fn to_perceived(&self, raw: f64) -> f64 {
+        let raw = raw.clamp(0.0, 1.0);
+
+        match *self {
+            Curve::Linear => raw,
+            Curve::Exponential => {
+                (1.0 + raw * (EXPONENTIAL_BASE - 1.0)).ln() / EXPONENTIAL_BASE.ln()
+            }
+            Curve::Gamma { exponent } => raw.powf(1.0 / exponent.max(f64::EPSILON)),
+        }
+    }
+
+    /// Raw brightness in [0->max] for a perceived fraction
+    pub fn raw_brightness(&self, perceived: f64, max: i16) -> i16 {
+        (self.to_raw(perceived) * max as f64).round() as i16
+    }
+
+    /// Perceived fraction of a raw brightness in [0->max]
+    pub fn perceived(&self, brightness: i16, max: i16) -> f64 {
+        if max <= 0 {
+            return 0.0;
+        }
+        self.to_perceived(brightness as f64 / max as f64)
+    }
+
+    /// Move `current` by `step` on the perceived scale. A non-zero step always
+    /// changes the raw value by at least 1 unless a bound is reached
+    pub fn step(&self, current: i16, step: f64, max: i16) -> i16 {
+        let mut perceived = self.perceived(current, max);
+        // continue from the multiple of the step that rounded to `current`, so
+        // rounding does not add up over presses
+        if step != 0.0 {
+            let on_grid = (perceived / step.abs()).round() * step.abs();
+            // within rounding of `current`, with room for float noise
+            if (self.to_raw(on_grid) * max as f64 - current as f64).abs() <= 0.5 + 1e-9 {
+                perceived = on_grid;
+            }
+        }
+        let target = self.raw_brightness(perceived + step, max);
+
+        if target == current && step > 0.0 {
+            current.saturating_add(1).min(max)
+        } else if target == current && step < 0.0 {
+            current.saturating_sub(1).max(0)
+        } else {
+            target
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURVES: [Curve; 4] = [
+        Curve::Linear,
+        Curve::Exponential,
+        Curve::Gamma { exponent: 2.2 },
+        Curve::Gamma { exponent: 0.5 },
+    ];
+
+    /// Maxima of keyboard LEDs, the patched module and backlights
+    const MAXIMA: [i16; 5] = [1, 3, 100, 255, 1023];
+
+    #[test]
+    fn to_perceived_inverts_to_raw() {
+        for curve in CURVES {
+            for i in 0..=1000 {
+                let x = i as f64 / 1000.0;
+                let back = curve.to_perceived(curve.to_raw(x));
+                assert!((back - x).abs() < 1e-9, "{}: {} -> {}", curve, x, back);
+            }
+        }
+    }
+
+    #[test]
+    fn ends_stay_at_the_ends() {
+        for curve in CURVES {
+            assert_eq!(curve.to_raw(0.0), 0.0);
+            assert!((curve.to_raw(1.0) - 1.0).abs() < 1e-12);
+            assert_eq!(curve.to_raw(-0.5), 0.0);
+            assert!((curve.to_raw(1.5) - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn every_raw_value_round_trips() {
+        for curve in CURVES {
+            for max in MAXIMA {
+                for brightness in 0..=max {
+                    let perceived = curve.perceived(brightness, max);
+                    assert_eq!(
+                        curve.raw_brightness(perceived, max),
+                        brightness,
+                        "{} at {}/{}",
+                        curve,
+                        brightness,
+                        max
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn presses_cover_the_whole_range() {
+        for curve in CURVES {
+            for max in [100, 255, 1023] {
+                for presses in 2..=40 {
+                    let step = 1.0 / presses as f64;
+
+                    let mut brightness = 0;
+                    for _ in 0..presses {
+                        brightness = curve.step(brightness, step, max);
+                    }
+                    assert_eq!(brightness, max, "{} up in {} of {}", curve, presses, max);
+
+                    for _ in 0..presses {
+                        brightness = curve.step(brightness, -step, max);
+                    }
+                    assert_eq!(brightness, 0, "{} down in {} of {}", curve, presses, max);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn steps_always_move_until_a_bound() {
+        for curve in CURVES {
+            assert!(curve.step(0, 0.001, 255) > 0);
+            assert!(curve.step(255, -0.001, 255) < 255);
+            assert_eq!(curve.step(100, 0.0, 255), 100);
+            assert_eq!(curve.step(255, 0.1, 255), 255);
+            assert_eq!(curve.step(0, -0.1, 255), 0);
+        }
+    }
+
+    #[test]
+    fn gamma_exponent_has_to_be_positive() {
+        for exponent in [0.0, -2.2, f64::NAN, f64::INFINITY] {
+            let err = Curve::Gamma { exponent }.validate().unwrap_err();
+            assert_eq!(err.exit_code(), 7);
+        }
+        for curve in CURVES {
+            assert!(curve.validate().is_ok());
+        }
+    }
+}
@@ -0,0 +1,452 @@
+//! Long running owner of the device, controlled over a Unix socket.
+//!
+//! The protocol is one request line per connection, answered with one JSON
+//! line. Requests are [`Action`]s in their text form (`get`, `set 40%`,
+//! `up`, ...) or `reload` to re-read the config. Replies look like
+//!
+//! ```text
+//! {"status":"ok","action":"up","changed":true,"message":"Brightness up to 115",...}
+//! {"status":"error","message":"Int out of range. Brightness is between [0->255] inclusive","exit_code":8}
+//! ```
+//!
+//! Requests are run one at a time, so concurrent hotkey presses cannot race
+//! on the brightness. A request that writes the device interrupts a running
+//! fade, reads and work of background features wait for it to finish.
+
+use crate::command::{Action, Outcome};
+use crate::config::{self, Config};
+use crate::error::{Result, ScreenpadError};
+use crate::fade::Fade;
+use crate::screenpad::Screenpad;
+use serde_derive::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+const SOCKET_NAME: &str = "screenpadctl.sock";
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client waits for the reply on top of the fade, so a stuck
+/// daemon cannot hang every hotkey
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `$XDG_RUNTIME_DIR/screenpadctl.sock`
+pub fn socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir.join(SOCKET_NAME),
+        _ => crate::state::runtime_dir().join(SOCKET_NAME),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Reply {
+    Ok(Outcome),
+    Error { message: String, exit_code: u8 },
+}
+
+impl Reply {
+    fn error(err: &ScreenpadError) -> Self {
+        Reply::Error {
+            message: err.to_string(),
+            exit_code: err.exit_code(),
+        }
+    }
+
+    /// Turn an error reply back into an error
+    pub fn into_result(self) -> Result<Outcome> {
+        match self {
+            Reply::Ok(outcome) => Ok(outcome),
+            Reply::Error { message, exit_code } => {
+                Err(ScreenpadError::Daemon { message, exit_code })
+            }
+        }
+    }
+}
+
+/// Time to wait for the reply to a request, which may run a fade of `fade`
+pub fn reply_timeout(fade: &Fade) -> Duration {
+    REPLY_TIMEOUT + Duration::from_millis(fade.duration_ms)
+}
+
+/// Send one request line to the daemon at `path`. Returns `None` when no
+/// daemon is listening, fails when it does not answer within `timeout`
+pub fn request(path: &Path, line: &str, timeout: Duration) -> Result<Option<Reply>> {
+    let io_error = |err| ScreenpadError::io(path, err);
+
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(io_error(err)),
+    };
+
+    stream.set_read_timeout(Some(timeout)).map_err(io_error)?;
+    stream.set_write_timeout(Some(timeout)).map_err(io_error)?;
+    writeln!(stream, "{}", line).map_err(io_error)?;
+
+    let mut reply = String::new();
+    match BufReader::new(stream).read_line(&mut reply) {
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            return Err(ScreenpadError::Daemon {
+                message: format!(
+                    "The daemon on {} did not answer within {}s",
+                    path.display(),
+                    timeout.as_secs_f64()
+                ),
+                exit_code: 1,
+            });
+        }
+        result => result.map_err(io_error)?,
+    };
+
+    serde_json::from_str(&reply)
+        .map(Some)
+        .map_err(|_| ScreenpadError::Parse {
+            path: path.to_path_buf(),
+            value: reply.trim_end().to_string(),
+        })
+}
+
+/// Handle one request line
+fn handle(screenpad: &mut Screenpad, cfg: &mut Config, line: &str) -> Reply {
+    let result = if line.trim() == "reload" {
+        config::load().and_then(|new_cfg| {
+            *cfg = new_cfg;
+            screenpad.apply_config(cfg);
+            screenpad.outcome("reload", false, "Config reloaded".to_string())
+        })
+    } else {
+        line.parse::<Action>()
+            .and_then(|action| screenpad.execute(action, cfg))
+    };
+
+    match result {
+        Ok(outcome) => Reply::Ok(outcome),
+        Err(err) => Reply::error(&err),
+    }
+}
+
+type Task = Box<dyn FnOnce(&mut Screenpad, &Config) + Send>;
+
+/// Work for the thread that owns the device
+enum Job {
+    Request(String, UnixStream),
+    Task(Task),
+}
+
+impl Job {
+    /// Whether the job takes over from a running fade. Only client requests
+    /// that write do, `get` and `reload` wait
+    fn interrupts(&self) -> bool {
+        match self {
+            Job::Request(line, _) => line.parse::<Action>().is_ok_and(|action| action.writes()),
+            Job::Task(_) => false,
+        }
+    }
+}
+
+/// Lets background features run code on the device owned by the daemon,
+/// in turn with client requests
+#[derive(Clone)]
+pub struct Tasks {
+    queue: mpsc::Sender<Job>,
+    /// Queued client requests that write
+    requests: Arc<AtomicUsize>,
+}
+
+impl Tasks {
+    fn push(&self, job: Job) -> bool {
+        let request = job.interrupts();
+        if request {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+        }
+        if self.queue.send(job).is_err() {
+            if request {
+                self.requests.fetch_sub(1, Ordering::SeqCst);
+            }
+            return false;
+        }
+        true
+    }
+
+    /// Queue `task`, returns false once the daemon is gone
+    pub fn submit(&self, task: impl FnOnce(&mut Screenpad, &Config) + Send + 'static) -> bool {
+        self.push(Job::Task(Box::new(task)))
+    }
+}
+
+/// Read the request of a client and queue it
+fn accept(stream: UnixStream, tasks: Tasks) {
+    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+
+    let mut line = String::new();
+    if BufReader::new(reader).read_line(&mut line).is_err() {
+        return;
+    }
+
+    tasks.push(Job::Request(line, stream));
+}
+
+/// Bind the socket at `path`, replacing a stale one
+fn bind(path: &Path) -> Result<UnixListener> {
+    let io_error = |err| ScreenpadError::io(path, err);
+
+    if UnixStream::connect(path).is_ok() {
+        return Err(ScreenpadError::Usage(format!(
+            "A daemon is already listening on {}",
+            path.display()
+        )));
+    }
+    if path.exists() {
+        fs::remove_file(path).map_err(io_error)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(io_error)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(io_error)?;
+    Ok(listener)
+}
+
+/// Serve requests on `path` until the process is killed. `spawn` starts
+/// background features, which get a [`Tasks`] handle to the device
+pub fn serve(
+    path: &Path,
+    screenpad: Screenpad,
+    mut cfg: Config,
+    spawn: impl FnOnce(&Tasks, &Config),
+) -> Result<()> {
+    let listener = bind(path)?;
+    let (queue, jobs) = mpsc::channel();
+    let tasks = Tasks {
+        queue,
+        requests: Arc::new(AtomicUsize::new(0)),
+    };
+
+    // a client waiting to write interrupts a running fade. Background tasks
+    // do not, an `off` cut short by a poll would leave the screen half faded
+    let requests = tasks.requests.clone();
+    let mut screenpad = screenpad.with_interrupt(move || requests.load(Ordering::SeqCst) > 0);
+
+    let listener_tasks = tasks.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tasks = listener_tasks.clone();
+            thread::spawn(move || accept(stream, tasks));
+        }
+    });
+
+    spawn(&tasks, &cfg);
+
+    for job in jobs {
+        if job.interrupts() {
+            tasks.requests.fetch_sub(1, Ordering::SeqCst);
+        }
+        match job {
+            Job::Request(line, mut stream) => {
+                let reply = handle(&mut screenpad, &mut cfg, &line);
+                let reply = serde_json::to_string(&reply).expect("replies always serialize");
+                // the client may have given up waiting, that is not our problem
+                let _ = writeln!(stream, "{}", reply);
+            }
+            Job::Task(task) => task(&mut screenpad, &cfg),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+    use std::time::Instant;
+
+    /// Daemon on a socket in `dir` serving an in-memory screenpad at 120 of
+    /// 255. Runs until the test process ends
+    fn start(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join(SOCKET_NAME);
+        let screenpad = testutil::screenpad(dir, 120, 255);
+        let socket = path.clone();
+        thread::spawn(move || serve(&socket, screenpad, Config::default(), |_, _| {}));
+
+        let started = Instant::now();
+        while UnixStream::connect(&path).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "daemon did not start"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+        path
+    }
+
+    fn send(path: &Path, line: &str) -> Reply {
+        request(path, line, Duration::from_secs(5))
+            .unwrap()
+            .expect("daemon listens")
+    }
+
+    fn exit_code(reply: Reply) -> u8 {
+        reply.into_result().unwrap_err().exit_code()
+    }
+
+    #[test]
+    fn handles_actions() {
+        let dir = TempDir::new();
+        let mut screenpad = testutil::screenpad(&dir, 120, 255);
+        let mut cfg = Config::default();
+
+        let Reply::Ok(outcome) = handle(&mut screenpad, &mut cfg, "set 40%\n") else {
+            panic!("set failed");
+        };
+        assert_eq!((outcome.action.as_str(), outcome.brightness), ("set", 102));
+
+        let Reply::Ok(outcome) = handle(&mut screenpad, &mut cfg, "get") else {
+            panic!("get failed");
+        };
+        assert!(!outcome.changed);
+        assert_eq!(outcome.brightness, 102);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let dir = TempDir::new();
+        let mut screenpad = testutil::screenpad(&dir, 120, 255);
+        let mut cfg = Config::default();
+
+        for line in [
+            "",
+            "\n",
+            "bogus",
+            "set",
+            "up 5",
+            "set 40% now",
+            "reload now",
+        ] {
+            let reply = handle(&mut screenpad, &mut cfg, line);
+            assert_eq!(exit_code(reply), 2, "{:?}", line);
+        }
+        let reply = handle(&mut screenpad, &mut cfg, "set 300");
+        assert_eq!(exit_code(reply), 8);
+        assert_eq!(screenpad.get_brightness().unwrap(), 120);
+    }
+
+    #[test]
+    fn replies_round_trip_as_json() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 120, 255);
+        let outcome = screenpad.execute(Action::Up, &Config::default()).unwrap();
+
+        for reply in [
+            Reply::Ok(outcome),
+            Reply::error(&ScreenpadError::Usage("Invalid command `x`".to_string())),
+        ] {
+            let line = serde_json::to_string(&reply).unwrap();
+            assert!(!line.contains('\n'));
+            assert_eq!(serde_json::from_str::<Reply>(&line).unwrap(), reply);
+        }
+
+        let line = r#"{"status":"error","message":"Int out of range","exit_code":8}"#;
+        assert_eq!(exit_code(serde_json::from_str(line).unwrap()), 8);
+        let line = r#"{"status":"ok","action":"get","changed":false,"message":"","brightness":1,"max_brightness":255,"state":"dim"}"#;
+        let Reply::Ok(outcome) = serde_json::from_str(line).unwrap() else {
+            panic!("not ok");
+        };
+        assert_eq!((outcome.backup, outcome.interrupted), (None, false));
+    }
+
+    #[test]
+    fn serves_requests_over_the_socket() {
+        let dir = TempDir::new();
+        let path = start(&dir);
+
+        let Reply::Ok(outcome) = send(&path, "up") else {
+            panic!("up failed");
+        };
+        assert_eq!(outcome.brightness, 135);
+        let Reply::Ok(outcome) = send(&path, "off") else {
+            panic!("off failed");
+        };
+        assert_eq!((outcome.brightness, outcome.backup), (0, Some(135)));
+
+        assert_eq!(exit_code(send(&path, "bogus")), 2);
+        assert_eq!(exit_code(send(&path, "set 40% 50%")), 2);
+    }
+
+    #[test]
+    fn refuses_a_second_daemon() {
+        let dir = TempDir::new();
+        let path = start(&dir);
+
+        assert_eq!(bind(&path).unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn replaces_a_stale_socket() {
+        let dir = TempDir::new();
+        let path = dir.write("run/screenpadctl.sock", "");
+
+        assert!(bind(&path).is_ok());
+    }
+
+    #[test]
+    fn no_daemon_is_no_reply() {
+        let dir = TempDir::new();
+        let path = dir.path().join(SOCKET_NAME);
+
+        assert!(request(&path, "get", Duration::from_secs(1))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn a_stuck_daemon_times_out() {
+        let dir = TempDir::new();
+        let path = dir.path().join(SOCKET_NAME);
+        let _listener = UnixListener::bind(&path).unwrap();
+
+        let started = Instant::now();
+        let err = request(&path, "get", Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.exit_code(), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn only_writing_requests_interrupt() {
+        let (stream, _peer) = UnixStream::pair().unwrap();
+        let request = |line: &str| Job::Request(line.to_string(), stream.try_clone().unwrap());
+
+        assert!(request("off\n").interrupts());
+        assert!(request("set 40%").interrupts());
+        assert!(!request("get\n").interrupts());
+        assert!(!request("reload\n").interrupts());
+        assert!(!request("bogus").interrupts());
+        assert!(!Job::Task(Box::new(|_, _| {})).interrupts());
+    }
+}
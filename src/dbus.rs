@@ -0,0 +1,456 @@
+//! D-Bus session service `org.screenpadctl.Screenpad`.
+//!
+//! The object at `/org/screenpadctl/Screenpad` has the read-only properties
+//! `Brightness`, `MaxBrightness` and `State`, and the methods `On`, `Off`,
+//! `Dim`, `Toggle`, `Cycle`, `Up`, `Down`, `Set(level)` and `Preset(name)`,
+//! where `level` takes the same forms as `screenpadctl set`. Methods return the message
+//! the command line would print. `PropertiesChanged` is emitted for
+//! `Brightness` and `State` whenever they change, whoever changed them.
+
+use crate::command::{Action, Outcome};
+use crate::config::Config;
+use crate::error::{Result, ScreenpadError};
+use crate::screenpad::{ScreenState, Screenpad};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+pub const SERVICE_NAME: &str = "org.screenpadctl.Screenpad";
+pub const OBJECT_PATH: &str = "/org/screenpadctl/Screenpad";
+
+/// Longest time between two checks for changes made elsewhere
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn failed(err: ScreenpadError) -> fdo::Error {
+    fdo::Error::Failed(err.to_string())
+}
+
+/// The device, shared with the threads actions run on
+struct Device {
+    screenpad: Screenpad,
+    cfg: Config,
+    /// Method calls may run concurrently, actions take turns
+    turn: Mutex<()>,
+    /// Method calls waiting for their turn
+    waiting: Arc<AtomicUsize>,
+}
+
+impl Device {
+    fn execute(&self, action: Action) -> Result<Outcome> {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let turn = self.turn.lock();
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+
+        let _turn = turn.expect("turn lock poisoned");
+        self.screenpad.execute(action, &self.cfg)
+    }
+
+    fn snapshot(&self) -> Result<(i16, ScreenState)> {
+        Ok((
+            self.screenpad.get_brightness()?,
+            self.screenpad.screen_state()?,
+        ))
+    }
+}
+
+pub struct Service {
+    device: Arc<Device>,
+    /// Brightness and state last announced with `PropertiesChanged`
+    announced: Mutex<Option<(i16, ScreenState)>>,
+}
+
+impl Service {
+    pub fn new(screenpad: Screenpad, cfg: Config) -> Self {
+        let waiting = Arc::new(AtomicUsize::new(0));
+
+        // a waiting call interrupts a running fade
+        let waiting_calls = waiting.clone();
+        let screenpad = screenpad.with_interrupt(move || waiting_calls.load(Ordering::SeqCst) > 0);
+
+        let device = Arc::new(Device {
+            screenpad,
+            cfg,
+            turn: Mutex::new(()),
+            waiting,
+        });
+        Self {
+            announced: Mutex::new(device.snapshot().ok()),
+            device,
+        }
+    }
+
+    /// Run `action` on a thread of its own, fades sleep between frames
+    async fn execute(&self, action: Action) -> Result<Outcome> {
+        let device = self.device.clone();
+        blocking::unblock(move || device.execute(action)).await
+    }
+
+    /// Emit `PropertiesChanged` for what changed since the last call
+    async fn announce(&self, emitter: &SignalEmitter<'_>) -> fdo::Result<()> {
+        let current = self.device.snapshot().map_err(failed)?;
+        let previous = self
+            .announced
+            .lock()
+            .expect("announce lock poisoned")
+            .replace(current);
+
+        if previous.map(|(brightness, _)| brightness) != Some(current.0) {
+            self.brightness_changed(emitter).await?;
+        }
+        if previous.map(|(_, state)| state) != Some(current.1) {
+            self.state_changed(emitter).await?;
+        }
+        Ok(())
+    }
+
+    /// Run `action` and announce the new property values
+    async fn call(&self, action: Action, emitter: &SignalEmitter<'_>) -> fdo::Result<String> {
+        let outcome = self.execute(action).await.map_err(failed)?;
+
+        // the action is done either way
+        if let Err(err) = self.announce(emitter).await {
+            eprintln!("dbus: {}", err);
+        }
+        Ok(outcome.message)
+    }
+}
+
+#[zbus::interface(name = "org.screenpadctl.Screenpad")]
+impl Service {
+    #[zbus(property)]
+    fn brightness(&self) -> fdo::Result<i16> {
+        self.device.screenpad.get_brightness().map_err(failed)
+    }
+
+    #[zbus(property)]
+    fn max_brightness(&self) -> fdo::Result<i16> {
+        self.device.screenpad.max_brightness().map_err(failed)
+    }
+
+    /// `on`, `dim` or `off`
+    #[zbus(property)]
+    fn state(&self) -> fdo::Result<String> {
+        let state = self.device.screenpad.screen_state().map_err(failed)?;
+        Ok(state.to_string())
+    }
+
+    async fn on(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<String> {
+        self.call(Action::On, &emitter).await
+    }
+
+    async fn off(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<String> {
+        self.call(Action::Off, &emitter).await
+    }
+
+    async fn dim(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<String> {
+        self.call(Action::Dim, &emitter).await
+    }
+
+    async fn toggle(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<String> {
+        self.call(Action::Toggle, &emitter).await
+    }
+
+    async fn cycle(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<String> {
+        self.call(Action::Cycle, &emitter).await
+    }
+
+    async fn up(&self, #[zbus(signal_emitter)] emitter: SignalEmitter<'_>) -> fdo::Result<String> {
+        self.call(Action::Up, &emitter).await
+    }
+
+    async fn down(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<String> {
+        self.call(Action::Down, &emitter).await
+    }
+
+    /// Set the brightness, e.g. `120`, `40%`, `+10%` or `max`
+    async fn set(
+        &self,
+        level: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<String> {
+        let level = level
+            .parse()
+            .map_err(|err: ScreenpadError| fdo::Error::InvalidArgs(err.to_string()))?;
+        self.call(Action::Set(level), &emitter).await
+    }
+
+    /// Go to a preset from the config, or to `on`, `dim` or `off`
+    async fn preset(
+        &self,
+        name: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<String> {
+        self.call(Action::Preset(name.to_string()), &emitter).await
+    }
+}
+
+/// Serve on the connection `builder` connects, announcing changes made
+/// elsewhere as they are seen
+fn start(
+    builder: zbus::blocking::connection::Builder,
+    screenpad: Screenpad,
+    cfg: Config,
+) -> Result<zbus::blocking::Connection> {
+    let dbus_error = |err: zbus::Error| ScreenpadError::Dbus(err.to_string());
+
+    let watcher = Arc::new(screenpad.watcher());
+    let connection = builder
+        .name(SERVICE_NAME)
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Service::new(screenpad, cfg)))
+        .and_then(|builder| builder.build())
+        .map_err(dbus_error)?;
+
+    let server = connection.inner().clone();
+    let announcer = async move {
+        let Ok(iface) = server
+            .object_server()
+            .interface::<_, Service>(OBJECT_PATH)
+            .await
+        else {
+            return;
+        };
+        loop {
+            let watcher = watcher.clone();
+            blocking::unblock(move || watcher.wait(WATCH_INTERVAL)).await;
+
+            if let Err(err) = iface.get().await.announce(iface.signal_emitter()).await {
+                eprintln!("dbus: {}", err);
+            }
+        }
+    };
+    connection
+        .inner()
+        .executor()
+        .spawn(announcer, "announce changes")
+        .detach();
+
+    Ok(connection)
+}
+
+/// Serve on the session bus until the process is killed
+pub fn serve(screenpad: Screenpad, cfg: Config) -> Result<()> {
+    let builder = zbus::blocking::connection::Builder::session()
+        .map_err(|err| ScreenpadError::Dbus(err.to_string()))?;
+    let _connection = start(builder, screenpad, cfg)?;
+
+    loop {
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fade::Fade;
+    use crate::testutil::{self, TempDir};
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+    use zbus::blocking::fdo::PropertiesProxy;
+    use zbus::blocking::{Connection, Proxy};
+    use zbus::proxy::CacheProperties;
+
+    const LED: &str = "class/leds/asus::screenpad";
+
+    /// A private bus, so tests neither need nor touch the session bus
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        /// `None` where dbus-daemon is not installed
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|err| eprintln!("skipped, no dbus-daemon: {}", err))
+                .ok()?;
+
+            let mut address = String::new();
+            let stdout = daemon.stdout.take().expect("piped stdout");
+            BufReader::new(stdout).read_line(&mut address).ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+
+        fn builder(&self) -> zbus::blocking::connection::Builder<'static> {
+            zbus::blocking::connection::Builder::address(self.address.as_str())
+                .expect("bus address parses")
+        }
+
+        fn client(&self) -> Connection {
+            self.builder().build().expect("client connects")
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// Screenpad in a fake sysfs tree at `brightness` of 255
+    fn screenpad(dir: &TempDir, brightness: i16) -> Screenpad {
+        let led = dir.path().join(LED);
+        fs::create_dir_all(&led).unwrap();
+        fs::write(led.join("max_brightness"), "255").unwrap();
+        fs::write(led.join("brightness"), brightness.to_string()).unwrap();
+        Screenpad::open(dir.path(), None)
+            .expect("fake screenpad opens")
+            .with_backup_file(dir.path().join("brightness_backup"))
+    }
+
+    /// Proxy reading properties fresh, not from the signal-fed cache
+    fn proxy(connection: &Connection) -> Proxy<'static> {
+        zbus::blocking::proxy::Builder::new(connection)
+            .destination(SERVICE_NAME)
+            .and_then(|builder| builder.path(OBJECT_PATH))
+            .and_then(|builder| builder.interface(SERVICE_NAME))
+            .map(|builder| builder.cache_properties(CacheProperties::No))
+            .and_then(|builder| builder.build())
+            .expect("proxy")
+    }
+
+    fn error_name(err: zbus::Error) -> String {
+        match err {
+            zbus::Error::MethodError(name, _, _) => name.to_string(),
+            err => panic!("not a method error: {}", err),
+        }
+    }
+
+    #[test]
+    fn methods_change_the_properties() {
+        let Some(bus) = Bus::start() else { return };
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+        let _service = start(bus.builder(), screenpad, Config::default()).unwrap();
+        let client = bus.client();
+        let screenpad = proxy(&client);
+
+        assert_eq!(screenpad.get_property::<i16>("Brightness").unwrap(), 200);
+        assert_eq!(screenpad.get_property::<i16>("MaxBrightness").unwrap(), 255);
+        assert_eq!(screenpad.get_property::<String>("State").unwrap(), "on");
+
+        let message: String = screenpad.call("Set", &("40%",)).unwrap();
+        assert_eq!(message, "Set brightness to 102");
+        assert_eq!(screenpad.get_property::<i16>("Brightness").unwrap(), 102);
+
+        let message: String = screenpad.call("Off", &()).unwrap();
+        assert_eq!(message, "Screen off");
+        assert_eq!(screenpad.get_property::<String>("State").unwrap(), "off");
+
+        let err = screenpad.call::<_, _, String>("Set", &("lots",));
+        assert_eq!(
+            error_name(err.unwrap_err()),
+            "org.freedesktop.DBus.Error.InvalidArgs"
+        );
+        let err = screenpad.call::<_, _, String>("Preset", &("nope",));
+        assert_eq!(
+            error_name(err.unwrap_err()),
+            "org.freedesktop.DBus.Error.Failed"
+        );
+    }
+
+    #[test]
+    fn changes_made_elsewhere_are_signalled() {
+        let Some(bus) = Bus::start() else { return };
+        let dir = TempDir::new();
+        let _service = start(bus.builder(), screenpad(&dir, 200), Config::default()).unwrap();
+        let client = bus.client();
+
+        let properties = PropertiesProxy::builder(&client)
+            .destination(SERVICE_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut changes = properties.receive_properties_changed().unwrap();
+
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            for change in &mut changes {
+                let args = change.args().expect("signal arguments");
+                let mut names: Vec<String> = args
+                    .changed_properties()
+                    .keys()
+                    .map(|name| name.to_string())
+                    .collect();
+                names.sort();
+                if sender.send(names).is_err() {
+                    return;
+                }
+            }
+        });
+
+        // as the command line or a hotkey would
+        fs::write(dir.path().join(LED).join("brightness"), "0").unwrap();
+
+        let mut names = Vec::new();
+        while names.len() < 2 {
+            let signalled = received
+                .recv_timeout(Duration::from_secs(5))
+                .expect("PropertiesChanged within the timeout");
+            names.extend(signalled);
+        }
+        names.sort();
+        assert_eq!(names, ["Brightness", "State"]);
+    }
+
+    #[test]
+    fn a_call_interrupts_a_running_fade() {
+        let Some(bus) = Bus::start() else { return };
+        let dir = TempDir::new();
+        let cfg = Config {
+            fade: Fade {
+                duration_ms: 5000,
+                ..Fade::default()
+            },
+            ..Config::default()
+        };
+        let screenpad = testutil::screenpad(&dir, 200, 255).with_fade(cfg.fade);
+        let _service = start(bus.builder(), screenpad, cfg).unwrap();
+
+        let address = bus.address.clone();
+        let fading = thread::spawn(move || {
+            let client = zbus::blocking::connection::Builder::address(address.as_str())
+                .unwrap()
+                .build()
+                .unwrap();
+            proxy(&client).call::<_, _, String>("Off", &()).unwrap()
+        });
+        thread::sleep(Duration::from_millis(300));
+
+        let client = bus.client();
+        let screenpad = proxy(&client);
+        let started = Instant::now();
+        // properties do not wait for the fade
+        screenpad.get_property::<i16>("Brightness").unwrap();
+        let message: String = screenpad.call("Set", &("max",)).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(message, "Set brightness to 255");
+        fading.join().unwrap();
+        assert_eq!(screenpad.get_property::<i16>("Brightness").unwrap(), 255);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::backend::{MockBackend, ScreenpadBackend, SysfsBacklight, SysfsLed};
+use crate::error::{Result, ScreenpadError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+/// Which sysfs class a device belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Led,
+    Backlight,
+}
+
+/// A screenpad node found in sysfs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub path: PathBuf,
+}
+
+impl Device {
+    pub fn open(&self) -> Box<dyn ScreenpadBackend> {
+        match self.kind {
+            DeviceKind::Led => Box::new(SysfsLed::new(&self.path)),
+            DeviceKind::Backlight => Box::new(SysfsBacklight::new(&self.path)),
+        }
+    }
+}
+
+/// Classes searched, in order of preference. The patched asus-wmi module
+/// creates an LED, mainline kernels a backlight
+const CLASSES: [(&str, DeviceKind); 2] = [
+    ("leds", DeviceKind::Led),
+    ("backlight", DeviceKind::Backlight),
+];
+
+/// Entries of a sysfs class directory, sorted so the pick is stable
+pub(crate) fn class_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+/// Find the screenpad under `<sysfs_root>/class/{leds,backlight}`
+pub fn discover(sysfs_root: &Path) -> Result<Device> {
+    for (class, kind) in CLASSES {
+        for path in class_entries(&sysfs_root.join("class").join(class)) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.contains("screenpad") && path.join("brightness").exists() {
+                return Ok(Device { kind, path });
+            }
+        }
+    }
+
+    Err(ScreenpadError::MissingDevice(format!(
+        "No screenpad device found in {0}/class/leds or {0}/class/backlight\n\
+         Make sure the asus-wmi module with screenpad support is loaded, \
+         or pass `--device <name>`",
+        sysfs_root.display()
+    )))
+}
+
+/// Resolve a `--device` override, either a path to the device directory or
+/// a device name such as `asus::screenpad` or `asus_screenpad`
+pub fn find_device(sysfs_root: &Path, device: &str) -> Result<Device> {
+    if device.contains('/') {
+        let path = PathBuf::from(device);
+        if !path.join("brightness").exists() {
+            return Err(ScreenpadError::MissingDevice(format!(
+                "{} is not a brightness device",
+                path.display()
+            )));
+        }
+
+        let in_backlight = path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .is_some_and(|class| class == "backlight");
+        let kind = if in_backlight {
+            DeviceKind::Backlight
+        } else {
+            DeviceKind::Led
+        };
+
+        return Ok(Device { kind, path });
+    }
+
+    for (class, kind) in CLASSES {
+        let path = sysfs_root.join("class").join(class).join(device);
+        if path.join("brightness").exists() {
+            return Ok(Device { kind, path });
+        }
+    }
+
+    Err(ScreenpadError::MissingDevice(format!(
+        "Device `{0}` not found in {1}/class/leds or {1}/class/backlight",
+        device,
+        sysfs_root.display()
+    )))
+}
+
+/// Find the LED class device `name`, e.g. `asus::kbd_backlight`
+pub fn find_led(sysfs_root: &Path, name: &str) -> Result<Device> {
+    let path = sysfs_root.join("class").join("leds").join(name);
+    if !path.join("brightness").exists() {
+        return Err(ScreenpadError::MissingDevice(format!(
+            "LED `{}` not found in {}/class/leds",
+            name,
+            sysfs_root.display()
+        )));
+    }
+
+    Ok(Device {
+        kind: DeviceKind::Led,
+        path,
+    })
+}
+
+/// Whether `SCREENPADCTL_BACKEND=mock` asks for the in-memory device
+pub fn mock_requested() -> bool {
+    std::env::var("SCREENPADCTL_BACKEND").as_deref() == Ok("mock")
+}
+
+/// Open the device to control. `SCREENPADCTL_BACKEND=mock` selects an
+/// in-memory device for running without the hardware
+pub fn open(sysfs_root: &Path, device: Option<&str>) -> Result<Box<dyn ScreenpadBackend>> {
+    if mock_requested() {
+        return Ok(Box::new(MockBackend::new(255, 255)));
+    }
+
+    let device = match device {
+        Some(device) => find_device(sysfs_root, device)?,
+        None => discover(sysfs_root)?,
+    };
+
+    Ok(device.open())
+}
+
+/// The backlight of the main panel, the first one that is not the screenpad
+pub fn primary_backlight(sysfs_root: &Path) -> Result<PathBuf> {
+    let class = sysfs_root.join("class").join("backlight");
+
+    class_entries(&class)
+        .into_iter()
+        .find(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            !name.contains("screenpad") && path.join("brightness").exists()
+        })
+        .ok_or_else(|| {
+            ScreenpadError::MissingDevice(format!(
+                "No main panel backlight found in {}",
+                class.display()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn device(dir: &TempDir, class: &str, name: &str) -> PathBuf {
+        let path = dir.write(&format!("class/{}/{}/brightness", class, name), "100\n");
+        dir.write(&format!("class/{}/{}/max_brightness", class, name), "255\n");
+        path.parent().unwrap().to_path_buf()
+    }
+
+    #[test]
+    fn prefers_the_led_over_the_backlight() {
+        let dir = TempDir::new();
+        device(&dir, "backlight", "asus_screenpad");
+        let led = device(&dir, "leds", "asus::screenpad");
+
+        assert_eq!(
+            discover(dir.path()).unwrap(),
+            Device {
+                kind: DeviceKind::Led,
+                path: led
+            }
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_backlight() {
+        let dir = TempDir::new();
+        device(&dir, "leds", "asus::kbd_backlight");
+        device(&dir, "backlight", "intel_backlight");
+        let backlight = device(&dir, "backlight", "asus_screenpad");
+
+        assert_eq!(
+            discover(dir.path()).unwrap(),
+            Device {
+                kind: DeviceKind::Backlight,
+                path: backlight
+            }
+        );
+    }
+
+    #[test]
+    fn skips_screenpads_without_brightness() {
+        let dir = TempDir::new();
+        dir.write("class/leds/asus::screenpad/max_brightness", "255\n");
+        let backlight = device(&dir, "backlight", "asus_screenpad");
+
+        assert_eq!(discover(dir.path()).unwrap().path, backlight);
+    }
+
+    #[test]
+    fn no_screenpad_is_a_missing_device() {
+        let dir = TempDir::new();
+        device(&dir, "backlight", "intel_backlight");
+
+        assert_eq!(discover(dir.path()).unwrap_err().exit_code(), 3);
+        assert_eq!(
+            discover(&dir.path().join("missing"))
+                .unwrap_err()
+                .exit_code(),
+            3
+        );
+    }
+
+    #[test]
+    fn finds_devices_by_name() {
+        let dir = TempDir::new();
+        let led = device(&dir, "leds", "asus::screenpad");
+        let backlight = device(&dir, "backlight", "asus_screenpad");
+
+        let found = find_device(dir.path(), "asus::screenpad").unwrap();
+        assert_eq!((found.kind, found.path), (DeviceKind::Led, led));
+        let found = find_device(dir.path(), "asus_screenpad").unwrap();
+        assert_eq!((found.kind, found.path), (DeviceKind::Backlight, backlight));
+        assert_eq!(find_device(dir.path(), "other").unwrap_err().exit_code(), 3);
+    }
+
+    #[test]
+    fn takes_the_kind_from_a_device_path() {
+        let dir = TempDir::new();
+        let backlight = device(&dir, "backlight", "asus_screenpad");
+        let led = device(&dir, "leds", "asus::screenpad");
+
+        let found = find_device(dir.path(), backlight.to_str().unwrap()).unwrap();
+        assert_eq!((found.kind, found.path), (DeviceKind::Backlight, backlight));
+        let found = find_device(dir.path(), led.to_str().unwrap()).unwrap();
+        assert_eq!((found.kind, found.path), (DeviceKind::Led, led));
+
+        let missing = dir.path().join("class/backlight/none");
+        let err = find_device(dir.path(), missing.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+    }
+
+    #[test]
+    fn opens_the_device_found() {
+        let dir = TempDir::new();
+        device(&dir, "backlight", "asus_screenpad");
+
+        let dev = discover(dir.path()).unwrap().open();
+        assert_eq!((dev.read().unwrap(), dev.max().unwrap()), (100, 255));
+    }
+
+    #[test]
+    fn finds_leds_and_the_main_panel() {
+        let dir = TempDir::new();
+        let kbd = device(&dir, "leds", "asus::kbd_backlight");
+        device(&dir, "backlight", "asus_screenpad");
+        let panel = device(&dir, "backlight", "intel_backlight");
+
+        assert_eq!(
+            find_led(dir.path(), "asus::kbd_backlight").unwrap().path,
+            kbd
+        );
+        assert_eq!(
+            find_led(dir.path(), "asus_screenpad")
+                .unwrap_err()
+                .exit_code(),
+            3
+        );
+        assert_eq!(primary_backlight(dir.path()).unwrap(), panel);
+    }
+}
@@ -0,0 +1,134 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, ScreenpadError>;
+
+/// Everything that can go wrong while controlling the screenpad.
+///
+/// Each variant maps to its own process exit code, see [`ScreenpadError::exit_code`]
+#[derive(Debug, Error)]
+pub enum ScreenpadError {
+    /// Bad command line, exit code 2
+    #[error("{0}")]
+    Usage(String),
+
+    /// No screenpad device, or the device vanished, exit code 3
+    #[error("{0}")]
+    MissingDevice(String),
+
+    /// Not allowed to read or write a file, exit code 4
+    #[error("Permission denied on {}\nAdd a udev rule or run as root", .0.display())]
+    PermissionDenied(PathBuf),
+
+    /// A file held something other than a number, exit code 5
+    #[error("Cannot parse `{value}` in {} as int", .path.display())]
+    Parse { path: PathBuf, value: String },
+
+    /// `on` without a stored brightness, exit code 6
+    #[error("No brightness backup in {}\nTurn the screen off first", .0.display())]
+    MissingBackup(PathBuf),
+
+    /// Config could not be loaded or stored, exit code 7
+    #[error("Config error")]
+    Config(#[from] confy::ConfyError),
+
+    /// Config loaded but holds a value that cannot work, exit code 7
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    /// Brightness outside [0->max], exit code 8
+    #[error("Int out of range. Brightness is between [0->{max}] inclusive")]
+    OutOfRange { value: i64, max: i16 },
+
+    /// Could not talk to the D-Bus session bus, exit code 9
+    #[error("D-Bus error: {0}")]
+    Dbus(String),
+
+    /// Error reported by the daemon, exits with the daemon's code
+    #[error("{message}")]
+    Daemon { message: String, exit_code: u8 },
+
+    /// Any other I/O failure, exit code 1
+    #[error("I/O error on {}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl ScreenpadError {
+    /// Attach `path` to an I/O error, picking the variant from its kind
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => ScreenpadError::PermissionDenied(path.to_path_buf()),
+            _ => ScreenpadError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    /// Like [`ScreenpadError::io`] for a file of a device, where a missing
+    /// file means the device is missing
+    pub fn device(path: &Path, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => {
+                ScreenpadError::MissingDevice(format!("{} does not exist", path.display()))
+            }
+            _ => Self::io(path, source),
+        }
+    }
+
+    /// Exit code of the command line tool for this error
+    ///
+    /// | code | meaning              |
+    /// |------|----------------------|
+    /// | 1    | other I/O error      |
+    /// | 2    | usage error          |
+    /// | 3    | missing device       |
+    /// | 4    | permission denied    |
+    /// | 5    | parse failure        |
+    /// | 6    | missing backup       |
+    /// | 7    | config error         |
+    /// | 8    | brightness out of range |
+    /// | 9    | D-Bus error          |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ScreenpadError::Io { .. } => 1,
+            ScreenpadError::Usage(_) => 2,
+            ScreenpadError::MissingDevice(_) => 3,
+            ScreenpadError::PermissionDenied(_) => 4,
+            ScreenpadError::Parse { .. } => 5,
+            ScreenpadError::MissingBackup(_) => 6,
+            ScreenpadError::Config(_) | ScreenpadError::InvalidConfig(_) => 7,
+            ScreenpadError::OutOfRange { .. } => 8,
+            ScreenpadError::Dbus(_) => 9,
+            ScreenpadError::Daemon { exit_code, .. } => *exit_code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_missing_devices_only_for_devices() {
+        let path = Path::new("/sys/class/leds/asus::screenpad/brightness");
+        let missing = || io::Error::from(io::ErrorKind::NotFound);
+
+        assert_eq!(ScreenpadError::io(path, missing()).exit_code(), 1);
+        assert_eq!(ScreenpadError::device(path, missing()).exit_code(), 3);
+    }
+
+    #[test]
+    fn permission_denied_keeps_its_exit_code() {
+        let path = Path::new("/sys/class/leds/asus::screenpad/brightness");
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+
+        assert_eq!(ScreenpadError::io(path, denied()).exit_code(), 4);
+        assert_eq!(ScreenpadError::device(path, denied()).exit_code(), 4);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::backend::ScreenpadBackend;
+use crate::curve::Curve;
+use crate::error::Result;
+use crate::state;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Time between two brightness writes of a fade
+const FRAME: Duration = Duration::from_millis(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    /// Progress of the fade in [0->1] for elapsed time `t` in [0->1]
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Fade settings, a duration of 0 jumps straight to the target
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct Fade {
+    pub duration_ms: u64,
+    pub easing: Easing,
+}
+
+/// Source of time for fades, so they can run without real sleeps
+pub trait Clock: Send + Sync {
+    /// Time since an arbitrary fixed point
+    fn now(&self) -> Duration;
+
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock that only moves when slept on
+#[derive(Default)]
+pub struct MockClock {
+    nanos: AtomicU64,
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Fade `dev` from `from` to `to`, stepping on the perceived scale of
+/// `curve`. Stops early when `interrupted` returns true and returns whether
+/// the target was reached
+pub fn run(
+    dev: &dyn ScreenpadBackend,
+    from: i16,
+    to: i16,
+    fade: &Fade,
+    curve: &Curve,
+    clock: &dyn Clock,
+    interrupted: &dyn Fn() -> bool,
+) -> Result<bool> {
+    let duration = Duration::from_millis(fade.duration_ms);
+    let max = dev.max()?;
+
+    if duration.is_zero() || from == to {
+        if interrupted() {
+            return Ok(false);
+        }
+        dev.write(to)?;
+        return Ok(true);
+    }
+
+    let start_perceived = curve.perceived(from, max);
+    let end_perceived = curve.perceived(to, max);
+    let start = clock.now();
+    let mut last = from;
+
+    loop {
+        if interrupted() {
+            return Ok(false);
+        }
+
+        let elapsed = clock.now().saturating_sub(start);
+        if elapsed >= duration {
+            break;
+        }
+
+        let progress = fade
+            .easing
+            .apply(elapsed.as_secs_f64() / duration.as_secs_f64());
+        let value = curve.raw_brightness(
+            start_perceived + (end_perceived - start_perceived) * progress,
+            max,
+        );
+        if value != last {
+            dev.write(value)?;
+            last = value;
+        }
+
+        clock.sleep(FRAME.min(duration - elapsed));
+    }
+
+    if last != to {
+        dev.write(to)?;
+    }
+    Ok(true)
+}
+
+/// Marker that lets only the newest command drive the device. Claiming it
+/// interrupts a fade still running in another process
+pub struct FadeToken {
+    path: PathBuf,
+    token: String,
+}
+
+impl FadeToken {
+    /// Take over from any running fade
+    pub fn claim() -> Result<Self> {
+        Self::claim_file("fade")
+    }
+
+    /// Take over from any running fade on the LED `name`
+    pub fn claim_led(name: &str) -> Result<Self> {
+        Self::claim_file(&format!("fade.{}", name))
+    }
+
+    fn claim_file(file_name: &str) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let token = Self {
+            path: state::runtime_dir().join(file_name),
+            token: format!("{} {}", std::process::id(), nanos),
+        };
+
+        state::write_atomic(&token.path, &token.token)?;
+        Ok(token)
+    }
+
+    /// Whether no later command has claimed the device
+    pub fn is_current(&self) -> bool {
+        fs::read_to_string(&self.path).is_ok_and(|token| token == self.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MockBackend;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    /// Mock device that keeps every write
+    struct Recorder {
+        dev: MockBackend,
+        writes: Mutex<Vec<i16>>,
+    }
+
+    impl Recorder {
+        fn new(brightness: i16, max: i16) -> Self {
+            Self {
+                dev: MockBackend::new(brightness, max),
+                writes: Mutex::default(),
+            }
+        }
+
+        fn writes(&self) -> Vec<i16> {
+            self.writes.lock().unwrap().clone()
+        }
+    }
+
+    impl ScreenpadBackend for Recorder {
+        fn read(&self) -> Result<i16> {
+            self.dev.read()
+        }
+
+        fn write(&self, value: i16) -> Result<()> {
+            self.writes.lock().unwrap().push(value);
+            self.dev.write(value)
+        }
+
+        fn max(&self) -> Result<i16> {
+            self.dev.max()
+        }
+    }
+
+    const EASINGS: [Easing; 4] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+    ];
+
+    fn fade(easing: Easing) -> Fade {
+        Fade {
+            duration_ms: 300,
+            easing,
+        }
+    }
+
+    #[test]
+    fn ends_exactly_on_the_target() {
+        for curve in [Curve::Linear, Curve::Gamma { exponent: 2.2 }] {
+            for (from, to) in [(0, 255), (255, 0), (200, 1), (7, 8)] {
+                let dev = Recorder::new(from, 255);
+                let clock = MockClock::default();
+
+                let reached = run(
+                    &dev,
+                    from,
+                    to,
+                    &fade(Easing::EaseInOut),
+                    &curve,
+                    &clock,
+                    &|| false,
+                );
+                assert!(reached.unwrap());
+                assert_eq!(dev.writes().last(), Some(&to));
+                assert_eq!(dev.read().unwrap(), to);
+                assert_eq!(clock.now(), Duration::from_millis(300));
+            }
+        }
+    }
+
+    #[test]
+    fn every_easing_moves_one_way() {
+        for easing in EASINGS {
+            let dev = Recorder::new(10, 255);
+            run(
+                &dev,
+                10,
+                240,
+                &fade(easing),
+                &Curve::Linear,
+                &MockClock::default(),
+                &|| false,
+            )
+            .unwrap();
+            let writes = dev.writes();
+            assert!(writes.len() > 10, "{:?} wrote {:?}", easing, writes);
+            assert!(
+                writes.windows(2).all(|w| w[0] < w[1]),
+                "{:?} up: {:?}",
+                easing,
+                writes
+            );
+
+            let dev = Recorder::new(240, 255);
+            run(
+                &dev,
+                240,
+                10,
+                &fade(easing),
+                &Curve::Linear,
+                &MockClock::default(),
+                &|| false,
+            )
+            .unwrap();
+            let writes = dev.writes();
+            assert!(
+                writes.windows(2).all(|w| w[0] > w[1]),
+                "{:?} down: {:?}",
+                easing,
+                writes
+            );
+        }
+    }
+
+    #[test]
+    fn easings_start_at_0_and_end_at_1() {
+        for easing in EASINGS {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert_eq!(easing.apply(-1.0), 0.0);
+            assert_eq!(easing.apply(2.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn an_interrupt_stops_further_writes() {
+        let dev = Recorder::new(0, 255);
+        let checks = AtomicUsize::new(0);
+        // let a few frames through, then interrupt
+        let interrupted = || checks.fetch_add(1, Ordering::SeqCst) >= 5;
+
+        let reached = run(
+            &dev,
+            0,
+            255,
+            &fade(Easing::Linear),
+            &Curve::Linear,
+            &MockClock::default(),
+            &interrupted,
+        );
+        assert!(!reached.unwrap());
+        let writes = dev.writes();
+        assert!(!writes.is_empty() && writes.len() <= 5, "{:?}", writes);
+        assert_ne!(dev.read().unwrap(), 255);
+    }
+
+    #[test]
+    fn an_interrupt_before_a_jump_writes_nothing() {
+        let dev = Recorder::new(100, 255);
+        let reached = run(
+            &dev,
+            100,
+            0,
+            &Fade::default(),
+            &Curve::Linear,
+            &MockClock::default(),
+            &|| true,
+        );
+
+        assert!(!reached.unwrap());
+        assert!(dev.writes().is_empty());
+        assert_eq!(dev.read().unwrap(), 100);
+    }
+
+    #[test]
+    fn no_duration_jumps_in_one_write() {
+        let dev = Recorder::new(100, 255);
+        let clock = MockClock::default();
+        run(
+            &dev,
+            100,
+            30,
+            &Fade::default(),
+            &Curve::Linear,
+            &clock,
+            &|| false,
+        )
+        .unwrap();
+
+        assert_eq!(dev.writes(), [30]);
+        assert_eq!(clock.now(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::backend::read_int;
+use crate::daemon::Tasks;
+use crate::discovery;
+use crate::error::Result;
+use crate::screenpad::Screenpad;
+use crate::watch;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Settings for mirroring the main panel backlight
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FollowConfig {
+    /// Follow inside `screenpadctl daemon`
+    pub enabled: bool,
+    /// Backlight to follow, e.g. `intel_backlight`. Detected when unset
+    pub source: Option<String>,
+    /// Screenpad fraction per main panel fraction
+    pub ratio: f64,
+    /// Percentage points added after the ratio
+    pub offset: f64,
+    /// Longest time between two checks of the main panel
+    pub poll_ms: u64,
+}
+
+impl Default for FollowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source: None,
+            ratio: 1.0,
+            offset: 0.0,
+            poll_ms: 500,
+        }
+    }
+}
+
+/// Mirrors the main panel backlight onto the screenpad
+#[derive(Clone)]
+pub struct Follower {
+    source: PathBuf,
+    ratio: f64,
+    offset: f64,
+    poll: Duration,
+}
+
+impl Follower {
+    pub fn new(sysfs_root: &Path, cfg: &FollowConfig) -> Result<Self> {
+        let source = match &cfg.source {
+            Some(name) => discovery::find_device(sysfs_root, name)?.path,
+            None => discovery::primary_backlight(sysfs_root)?,
+        };
+
+        Ok(Self {
+            source,
+            ratio: cfg.ratio,
+            offset: cfg.offset,
+            poll: Duration::from_millis(cfg.poll_ms.max(1)),
+        })
+    }
+
+    /// Main panel brightness as fraction of its maximum. Panels often go
+    /// beyond the range of the screenpad, so this does not use i16
+    pub fn source_fraction(&self) -> Result<f64> {
+        let brightness: i64 = read_int(&self.source.join("brightness"))?;
+        let max: i64 = read_int(&self.source.join("max_brightness"))?;
+
+        Ok(brightness as f64 / max.max(1) as f64)
+    }
+
+    /// Screenpad brightness for a main panel fraction. Never 0, following
+    /// must not turn the screenpad off
+    pub fn target(&self, source_fraction: f64, max: i16) -> i16 {
+        let fraction = (source_fraction * self.ratio + self.offset / 100.0).clamp(0.0, 1.0);
+        ((fraction * max as f64).round() as i16).max(1)
+    }
+
+    /// Mirror the main panel once, staying above the dim threshold. Returns
+    /// the new brightness, or `None` when the screenpad is off, dimmed or
+    /// already there
+    pub fn apply(&self, screenpad: &Screenpad) -> Result<Option<i16>> {
+        let target = self.target(self.source_fraction()?, screenpad.max_brightness()?);
+        screenpad.adjust(target)
+    }
+
+    /// Block until the main panel brightness differs from `last`
+    fn wait(&self, last: &mut Option<f64>) {
+        loop {
+            let fraction = self.source_fraction().ok();
+            if fraction != *last {
+                *last = fraction;
+                return;
+            }
+            watch::wait_for_change(&self.source.join("actual_brightness"), self.poll);
+        }
+    }
+
+    /// Follow until an error occurs
+    pub fn run(&self, screenpad: &Screenpad) -> Result<()> {
+        let mut last = None;
+        loop {
+            self.wait(&mut last);
+            self.apply(screenpad)?;
+        }
+    }
+
+    /// Follow in the background of the daemon
+    pub fn spawn(self, tasks: &Tasks) {
+        let tasks = tasks.clone();
+
+        thread::spawn(move || {
+            let mut last = None;
+            loop {
+                self.wait(&mut last);
+
+                let follower = self.clone();
+                let submitted = tasks.submit(move |screenpad, _| {
+                    if let Err(err) = follower.apply(screenpad) {
+                        eprintln!("follow: {}", err);
+                    }
+                });
+                if !submitted {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+
+    fn follower(dir: &TempDir, brightness: i64) -> Follower {
+        dir.write("class/backlight/intel_backlight/max_brightness", "19200\n");
+        dir.write(
+            "class/backlight/intel_backlight/brightness",
+            &format!("{}\n", brightness),
+        );
+        Follower::new(dir.path(), &FollowConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn mirrors_the_main_panel() {
+        let dir = TempDir::new();
+        let follower = follower(&dir, 9600);
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        assert_eq!(follower.apply(&screenpad).unwrap(), Some(128));
+        assert_eq!(follower.apply(&screenpad).unwrap(), None);
+    }
+
+    #[test]
+    fn ratio_and_offset_shape_the_target() {
+        let dir = TempDir::new();
+        let cfg = FollowConfig {
+            ratio: 0.5,
+            offset: 10.0,
+            ..FollowConfig::default()
+        };
+        dir.write("class/backlight/intel_backlight/max_brightness", "100\n");
+        dir.write("class/backlight/intel_backlight/brightness", "100\n");
+        let follower = Follower::new(dir.path(), &cfg).unwrap();
+
+        assert_eq!(follower.target(1.0, 100), 60);
+        assert_eq!(follower.target(0.0, 100), 10);
+        assert_eq!(
+            Follower::new(dir.path(), &FollowConfig::default())
+                .unwrap()
+                .target(0.0, 100),
+            1
+        );
+    }
+
+    #[test]
+    fn a_dark_panel_keeps_the_screenpad_on() {
+        let dir = TempDir::new();
+        let follower = follower(&dir, 0);
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        assert_eq!(follower.apply(&screenpad).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn leaves_a_dimmed_or_off_screen_alone() {
+        let dir = TempDir::new();
+        let follower = follower(&dir, 19200);
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+
+        screenpad.dim().unwrap();
+        assert_eq!(follower.apply(&screenpad).unwrap(), None);
+        screenpad.off().unwrap();
+        assert_eq!(follower.apply(&screenpad).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+    }
+}
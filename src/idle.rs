@@ -0,0 +1,455 @@
+use crate::daemon::Tasks;
+use crate::discovery::class_entries;
+use crate::error::{Result, ScreenpadError};
+use crate::fade::Clock;
+use crate::screenpad::{ScreenState, Screenpad, Transition};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const INPUT_DIR: &str = "/dev/input";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleSourceKind {
+    /// Events on input devices
+    #[default]
+    Evdev,
+    /// `IdleHint` of the logind session, needs the `dbus` feature
+    Logind,
+}
+
+/// Settings for dimming and turning off the screenpad without input
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Watch for idleness inside `screenpadctl daemon`
+    pub enabled: bool,
+    /// Seconds without input before dimming, 0 never dims
+    pub dim_after_s: u64,
+    /// Seconds without input before turning off, 0 never turns off
+    pub off_after_s: u64,
+    pub source: IdleSourceKind,
+    /// Input devices for `evdev`, e.g. `event3` or a path. All when empty
+    pub devices: Vec<String>,
+    pub poll_ms: u64,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dim_after_s: 60,
+            off_after_s: 300,
+            source: IdleSourceKind::Evdev,
+            devices: Vec::new(),
+            poll_ms: 500,
+        }
+    }
+}
+
+/// Tells how long the user has been away
+pub trait IdleSource: Send {
+    /// Time since the last input
+    fn idle_for(&mut self) -> Result<Duration>;
+}
+
+/// Input activity read from evdev devices
+pub struct Evdev {
+    last_input: Arc<Mutex<Instant>>,
+}
+
+impl Evdev {
+    /// Listen on `devices`, or on every `/dev/input/event*` when empty
+    pub fn open(devices: &[String]) -> Result<Self> {
+        let paths: Vec<PathBuf> = if devices.is_empty() {
+            class_entries(Path::new(INPUT_DIR))
+                .into_iter()
+                .filter(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+                })
+                .collect()
+        } else {
+            devices
+                .iter()
+                .map(|device| match device.contains('/') {
+                    true => PathBuf::from(device),
+                    false => Path::new(INPUT_DIR).join(device),
+                })
+                .collect()
+        };
+
+        let last_input = Arc::new(Mutex::new(Instant::now()));
+        let mut error = None;
+        let mut listening = 0;
+
+        for path in paths {
+            match File::open(&path) {
+                Ok(file) => {
+                    let last_input = last_input.clone();
+                    thread::spawn(move || Self::listen(file, &last_input));
+                    listening += 1;
+                }
+                Err(err) => error = Some(ScreenpadError::device(&path, err)),
+            }
+        }
+
+        match (listening, error) {
+            (0, Some(err)) => Err(err),
+            (0, None) => Err(ScreenpadError::MissingDevice(format!(
+                "No input devices found in {}",
+                INPUT_DIR
+            ))),
+            _ => Ok(Self { last_input }),
+        }
+    }
+
+    /// Note the time of every event until the device goes away
+    fn listen(mut file: File, last_input: &Mutex<Instant>) {
+        let mut events = [0; 1024];
+        while let Ok(1..) = file.read(&mut events) {
+            *last_input.lock().expect("input lock poisoned") = Instant::now();
+        }
+    }
+}
+
+impl IdleSource for Evdev {
+    fn idle_for(&mut self) -> Result<Duration> {
+        Ok(self
+            .last_input
+            .lock()
+            .expect("input lock poisoned")
+            .elapsed())
+    }
+}
+
+/// Idleness as reported to logind by the desktop session
+#[cfg(feature = "dbus")]
+pub struct Logind {
+    session: zbus::blocking::Proxy<'static>,
+}
+
+#[cfg(feature = "dbus")]
+impl Logind {
+    pub fn connect() -> Result<Self> {
+        let dbus_error = |err: zbus::Error| ScreenpadError::Dbus(err.to_string());
+
+        let connection = zbus::blocking::Connection::system().map_err(dbus_error)?;
+        let session = zbus::blocking::Proxy::new_owned(
+            connection,
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1/session/auto",
+            "org.freedesktop.login1.Session",
+        )
+        .map_err(dbus_error)?;
+        Ok(Self { session })
+    }
+}
+
+#[cfg(feature = "dbus")]
+impl IdleSource for Logind {
+    fn idle_for(&mut self) -> Result<Duration> {
+        let dbus_error = |err: zbus::Error| ScreenpadError::Dbus(err.to_string());
+
+        if !self
+            .session
+            .get_property::<bool>("IdleHint")
+            .map_err(dbus_error)?
+        {
+            return Ok(Duration::ZERO);
+        }
+        let since: u64 = self
+            .session
+            .get_property("IdleSinceHintMonotonic")
+            .map_err(dbus_error)?;
+
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `now` is a valid timespec to write to
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+
+        Ok(now.saturating_sub(Duration::from_micros(since)))
+    }
+}
+
+/// Idle source driven by hand, for tests and simulations
+#[derive(Clone)]
+pub struct SimulatedIdle {
+    clock: Arc<dyn Clock>,
+    last_input: Arc<Mutex<Duration>>,
+}
+
+impl SimulatedIdle {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        Self {
+            clock,
+            last_input: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Pretend the user touched something
+    pub fn input(&self) {
+        *self.last_input.lock().expect("input lock poisoned") = self.clock.now();
+    }
+}
+
+impl IdleSource for SimulatedIdle {
+    fn idle_for(&mut self) -> Result<Duration> {
+        let last_input = *self.last_input.lock().expect("input lock poisoned");
+        Ok(self.clock.now().saturating_sub(last_input))
+    }
+}
+
+/// Open the source selected in `cfg`
+pub fn open_source(cfg: &IdleConfig) -> Result<Box<dyn IdleSource>> {
+    match cfg.source {
+        IdleSourceKind::Evdev => Ok(Box::new(Evdev::open(&cfg.devices)?)),
+        #[cfg(feature = "dbus")]
+        IdleSourceKind::Logind => Ok(Box::new(Logind::connect()?)),
+        #[cfg(not(feature = "dbus"))]
+        IdleSourceKind::Logind => Err(ScreenpadError::Usage(
+            "The logind idle source needs screenpadctl built with the dbus feature".to_string(),
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdleStage {
+    Active,
+    Dimmed,
+    Off,
+}
+
+/// Picks the stage for the time without input
+pub struct IdleTimer {
+    dim_after: Option<Duration>,
+    off_after: Option<Duration>,
+    stage: IdleStage,
+}
+
+impl IdleTimer {
+    pub fn new(cfg: &IdleConfig) -> Self {
+        let after = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+
+        Self {
+            dim_after: after(cfg.dim_after_s),
+            off_after: after(cfg.off_after_s),
+            stage: IdleStage::Active,
+        }
+    }
+
+    pub fn stage_for(&self, idle: Duration) -> IdleStage {
+        if self.off_after.is_some_and(|after| idle >= after) {
+            IdleStage::Off
+        } else if self.dim_after.is_some_and(|after| idle >= after) {
+            IdleStage::Dimmed
+        } else {
+            IdleStage::Active
+        }
+    }
+
+    /// Returns the new stage when `idle` moves into another one
+    pub fn update(&mut self, idle: Duration) -> Option<IdleStage> {
+        let stage = self.stage_for(idle);
+        if stage == self.stage {
+            return None;
+        }
+        self.stage = stage;
+        Some(stage)
+    }
+}
+
+/// Moves the screenpad through idle stages. Only a screen dimmed or turned
+/// off here is turned back on, one the user turned off stays off
+#[derive(Clone, Default)]
+pub struct IdleActions {
+    acted: Arc<AtomicBool>,
+}
+
+impl IdleActions {
+    pub fn apply(&self, screenpad: &Screenpad, stage: IdleStage) -> Result<Option<Transition>> {
+        let state = screenpad.screen_state()?;
+        let acted = self.acted.load(Ordering::SeqCst);
+
+        let transition = match stage {
+            // on restores the brightness backed up when leaving on
+            IdleStage::Active if acted => screenpad.on()?,
+            IdleStage::Dimmed if state == ScreenState::On => screenpad.dim()?,
+            IdleStage::Off if state == ScreenState::On || acted => screenpad.off()?,
+            _ => return Ok(None),
+        };
+
+        self.acted
+            .store(stage != IdleStage::Active, Ordering::SeqCst);
+        Ok(Some(transition))
+    }
+}
+
+/// Watch for idleness in the background of the daemon
+pub fn spawn(tasks: &Tasks, mut source: Box<dyn IdleSource>, cfg: &IdleConfig) {
+    let tasks = tasks.clone();
+    let mut timer = IdleTimer::new(cfg);
+    let actions = IdleActions::default();
+    let poll = Duration::from_millis(cfg.poll_ms.max(1));
+
+    thread::spawn(move || loop {
+        match source.idle_for() {
+            Ok(idle) => {
+                if let Some(stage) = timer.update(idle) {
+                    let actions = actions.clone();
+                    let submitted = tasks.submit(move |screenpad, _| {
+                        if let Err(err) = actions.apply(screenpad, stage) {
+                            eprintln!("idle: {}", err);
+                        }
+                    });
+                    if !submitted {
+                        return;
+                    }
+                }
+            }
+            Err(err) => eprintln!("idle: {}", err),
+        }
+        thread::sleep(poll);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fade::MockClock;
+    use crate::testutil::{self, TempDir};
+
+    /// Idle timer and actions on a simulated user
+    struct Session {
+        clock: Arc<MockClock>,
+        source: SimulatedIdle,
+        timer: IdleTimer,
+        actions: IdleActions,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            let clock = Arc::new(MockClock::default());
+            Self {
+                source: SimulatedIdle::new(clock.clone()),
+                clock,
+                timer: IdleTimer::new(&IdleConfig::default()),
+                actions: IdleActions::default(),
+            }
+        }
+
+        /// Let `secs` pass and act on the stage the timer moves into
+        fn wait(&mut self, screenpad: &Screenpad, secs: u64) -> Option<Transition> {
+            self.clock.advance(Duration::from_secs(secs));
+            self.act(screenpad)
+        }
+
+        fn input(&mut self, screenpad: &Screenpad) -> Option<Transition> {
+            self.source.input();
+            self.act(screenpad)
+        }
+
+        fn act(&mut self, screenpad: &Screenpad) -> Option<Transition> {
+            let stage = self.timer.update(self.source.idle_for().unwrap())?;
+            self.actions.apply(screenpad, stage).unwrap()
+        }
+    }
+
+    #[test]
+    fn stages_follow_the_timeouts() {
+        let timer = IdleTimer::new(&IdleConfig::default());
+
+        assert_eq!(timer.stage_for(Duration::from_secs(59)), IdleStage::Active);
+        assert_eq!(timer.stage_for(Duration::from_secs(60)), IdleStage::Dimmed);
+        assert_eq!(timer.stage_for(Duration::from_secs(299)), IdleStage::Dimmed);
+        assert_eq!(timer.stage_for(Duration::from_secs(300)), IdleStage::Off);
+    }
+
+    #[test]
+    fn zero_disables_a_stage() {
+        let timer = IdleTimer::new(&IdleConfig {
+            dim_after_s: 0,
+            ..IdleConfig::default()
+        });
+        assert_eq!(timer.stage_for(Duration::from_secs(200)), IdleStage::Active);
+        assert_eq!(timer.stage_for(Duration::from_secs(300)), IdleStage::Off);
+
+        let timer = IdleTimer::new(&IdleConfig {
+            off_after_s: 0,
+            ..IdleConfig::default()
+        });
+        assert_eq!(
+            timer.stage_for(Duration::from_secs(3600)),
+            IdleStage::Dimmed
+        );
+    }
+
+    #[test]
+    fn timer_reports_each_stage_once() {
+        let mut timer = IdleTimer::new(&IdleConfig::default());
+
+        assert_eq!(timer.update(Duration::from_secs(10)), None);
+        assert_eq!(
+            timer.update(Duration::from_secs(60)),
+            Some(IdleStage::Dimmed)
+        );
+        assert_eq!(timer.update(Duration::from_secs(90)), None);
+        assert_eq!(timer.update(Duration::ZERO), Some(IdleStage::Active));
+    }
+
+    #[test]
+    fn dims_turns_off_and_wakes_up() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255);
+        let mut session = Session::new();
+
+        assert_eq!(session.wait(&screenpad, 30), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+
+        let dimmed = session.wait(&screenpad, 30).unwrap();
+        assert_eq!(dimmed.to, ScreenState::Dim);
+        assert_eq!(screenpad.get_brightness().unwrap(), 1);
+
+        let off = session.wait(&screenpad, 240).unwrap();
+        assert_eq!((off.from, off.to), (ScreenState::Dim, ScreenState::Off));
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+
+        let on = session.input(&screenpad).unwrap();
+        assert_eq!(on.to, ScreenState::On);
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+    }
+
+    #[test]
+    fn input_while_dimmed_restores_the_brightness() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255);
+        let mut session = Session::new();
+
+        session.wait(&screenpad, 60).unwrap();
+        assert_eq!(session.input(&screenpad).unwrap().to, ScreenState::On);
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+    }
+
+    #[test]
+    fn a_screen_turned_off_by_hand_stays_off() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255);
+        let mut session = Session::new();
+
+        screenpad.off().unwrap();
+        assert_eq!(session.wait(&screenpad, 60), None);
+        assert_eq!(session.wait(&screenpad, 240), None);
+        assert_eq!(session.input(&screenpad), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+    }
+}
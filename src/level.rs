@@ -0,0 +1,246 @@
+use crate::curve::Curve;
+use crate::error::{Result, ScreenpadError};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A brightness given on the command line, resolved against the device
+/// range with [`Level::resolve`]
+///
+/// | input   | meaning                         |
+/// |---------|---------------------------------|
+/// | `120`   | raw value                       |
+/// | `40%`   | percent of the maximum          |
+/// | `0.5`   | fraction of the maximum         |
+/// | `+10`   | raw step up, `-10` down         |
+/// | `+10%`  | step of 10% of the maximum      |
+/// | `max`   | device maximum, `min` is 0      |
+///
+/// Percentages and fractions are on the perceived scale of the [`Curve`].
+/// In the config a level is written in its text form, e.g. `"40%"`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Level {
+    Raw(i64),
+    /// Fraction of the maximum in [0->1]
+    Fraction(f64),
+    RawStep(i64),
+    /// Signed step as fraction of the maximum
+    FractionStep(f64),
+    Max,
+    Min,
+}
+
+fn invalid(input: &str) -> ScreenpadError {
+    ScreenpadError::Usage(format!(
+        "Invalid brightness `{}`. Use an int, a percentage like 40%, a fraction like 0.5, \
+         a step like +10 or -10%, `max` or `min`",
+        input
+    ))
+}
+
+/// Parse an unsigned number as raw value or fraction
+fn parse_magnitude(text: &str, input: &str) -> Result<(f64, bool)> {
+    if let Some(percent) = text.strip_suffix('%') {
+        let value = percent.parse::<f64>().map_err(|_| invalid(input))?;
+        return Ok((value / 100.0, true));
+    }
+
+    if text.contains('.') {
+        let value = text.parse::<f64>().map_err(|_| invalid(input))?;
+        return Ok((value, true));
+    }
+
+    // digits only, so `+` or `-` inside the number are rejected here
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid(input));
+    }
+    // anything that does not fit is far beyond any device maximum
+    let value = text.parse::<i64>().unwrap_or(i64::MAX);
+    Ok((value as f64, false))
+}
+
+impl FromStr for Level {
+    type Err = ScreenpadError;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let text = input.trim();
+
+        match text.to_ascii_lowercase().as_str() {
+            "max" => return Ok(Level::Max),
+            "min" => return Ok(Level::Min),
+            _ => {}
+        }
+
+        let (sign, magnitude) = match text.as_bytes().first() {
+            Some(b'+') => (Some(1), &text[1..]),
+            Some(b'-') => (Some(-1), &text[1..]),
+            _ => (None, text),
+        };
+
+        let (value, is_fraction) = parse_magnitude(magnitude, input)?;
+        if !value.is_finite() || value < 0.0 {
+            return Err(invalid(input));
+        }
+
+        Ok(match (sign, is_fraction) {
+            (None, true) => Level::Fraction(value),
+            (None, false) => Level::Raw(value as i64),
+            (Some(sign), true) => Level::FractionStep(sign as f64 * value),
+            (Some(sign), false) => Level::RawStep(sign * value as i64),
+        })
+    }
+}
+
+impl TryFrom<String> for Level {
+    type Error = ScreenpadError;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<Level> for String {
+    fn from(level: Level) -> Self {
+        level.to_string()
+    }
+}
+
+/// Percent of a fraction without float noise such as `7.000000000000001`
+fn percent(fraction: f64) -> f64 {
+    (fraction * 1e8).round() / 1e6
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Raw(value) => write!(f, "{}", value),
+            Level::Fraction(value) => write!(f, "{}%", percent(*value)),
+            Level::RawStep(value) => write!(f, "{:+}", value),
+            Level::FractionStep(value) => write!(f, "{:+}%", percent(*value)),
+            Level::Max => f.write_str("max"),
+            Level::Min => f.write_str("min"),
+        }
+    }
+}
+
+impl Level {
+    /// Raw brightness for a device at `current` with range [0->max].
+    /// Absolute values outside the range fail with `OutOfRange`, steps
+    /// saturate at the bounds
+    pub fn resolve(&self, current: i16, max: i16, curve: &Curve) -> Result<i16> {
+        let out_of_range = |value: i64| ScreenpadError::OutOfRange { value, max };
+
+        let value = match *self {
+            Level::Max => return Ok(max),
+            Level::Min => return Ok(0),
+            Level::Raw(value) => value,
+            Level::Fraction(fraction) => {
+                if fraction > 1.0 {
+                    return Err(out_of_range((fraction * max as f64).round() as i64));
+                }
+                return Ok(curve.raw_brightness(fraction, max));
+            }
+            Level::RawStep(step) => {
+                return Ok((current as i64).saturating_add(step).clamp(0, max as i64) as i16)
+            }
+            Level::FractionStep(step) => return Ok(curve.step(current, step, max)),
+        };
+
+        if !(0..=max as i64).contains(&value) {
+            return Err(out_of_range(value));
+        }
+        Ok(value as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Level {
+        text.parse().expect("valid level")
+    }
+
+    #[test]
+    fn parses_every_form() {
+        assert_eq!(parse("120"), Level::Raw(120));
+        assert_eq!(parse("40%"), Level::Fraction(0.4));
+        assert_eq!(parse("0.5"), Level::Fraction(0.5));
+        assert_eq!(parse("1.0"), Level::Fraction(1.0));
+        assert_eq!(parse("150%"), Level::Fraction(1.5));
+        assert_eq!(parse("+10%"), Level::FractionStep(0.1));
+        assert_eq!(parse("-25"), Level::RawStep(-25));
+        assert_eq!(parse("+10"), Level::RawStep(10));
+        assert_eq!(parse("max"), Level::Max);
+        assert_eq!(parse("MIN"), Level::Min);
+        assert_eq!(parse(" 7 "), Level::Raw(7));
+    }
+
+    #[test]
+    fn overflow_saturates_instead_of_failing_to_parse() {
+        assert_eq!(parse("99999999999999999999"), Level::Raw(i64::MAX));
+        assert_eq!(parse("+99999999999999999999"), Level::RawStep(i64::MAX));
+        assert_eq!(parse("-99999999999999999999"), Level::RawStep(-i64::MAX));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in [
+            "", "--", "+", "-", "1e3", "+-5", "abc", "5%%", "-%", "NaN", "inf", "1.2.3",
+        ] {
+            let err = text.parse::<Level>().unwrap_err();
+            assert_eq!(err.exit_code(), 2, "`{}` parsed", text);
+        }
+    }
+
+    #[test]
+    fn text_form_round_trips() {
+        for text in ["120", "40%", "+10", "-10", "+12.5%", "-25%", "max", "min"] {
+            assert_eq!(parse(text).to_string(), text);
+        }
+        assert_eq!(Level::Fraction(0.07).to_string(), "7%");
+    }
+
+    #[test]
+    fn absolute_values_outside_the_range_fail() {
+        let curve = Curve::Linear;
+
+        assert_eq!(parse("255").resolve(0, 255, &curve).unwrap(), 255);
+        assert_eq!(parse("1.0").resolve(0, 255, &curve).unwrap(), 255);
+        for text in ["256", "150%", "99999999999999999999"] {
+            let err = parse(text).resolve(0, 255, &curve).unwrap_err();
+            assert_eq!(err.exit_code(), 8, "`{}` resolved", text);
+        }
+    }
+
+    #[test]
+    fn steps_saturate_at_the_bounds() {
+        let curve = Curve::Linear;
+
+        assert_eq!(parse("+10").resolve(250, 255, &curve).unwrap(), 255);
+        assert_eq!(parse("-10").resolve(5, 255, &curve).unwrap(), 0);
+        assert_eq!(parse("+50%").resolve(200, 255, &curve).unwrap(), 255);
+        assert_eq!(parse("-50%").resolve(50, 255, &curve).unwrap(), 0);
+        assert_eq!(
+            parse("+99999999999999999999")
+                .resolve(0, 255, &curve)
+                .unwrap(),
+            255
+        );
+        assert_eq!(
+            parse("-99999999999999999999")
+                .resolve(255, 255, &curve)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn max_and_min_resolve_to_the_bounds() {
+        let curve = Curve::Gamma { exponent: 2.2 };
+
+        assert_eq!(Level::Max.resolve(3, 255, &curve).unwrap(), 255);
+        assert_eq!(Level::Min.resolve(3, 255, &curve).unwrap(), 0);
+    }
+}
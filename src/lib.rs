@@ -0,0 +1,38 @@
+//! Control the screenpad on ASUS Zenbook Duo devices
+//!
+//! ```no_run
+//! let screenpad = screenpadctl::Screenpad::discover().unwrap();
+//! screenpad.overwrite_brightness(100).unwrap();
+//! ```
+
+pub mod als;
+pub mod backend;
+pub mod command;
+pub mod config;
+pub mod curve;
+pub mod daemon;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+pub mod discovery;
+pub mod error;
+pub mod fade;
+pub mod follow;
+pub mod idle;
+pub mod level;
+pub mod power;
+pub mod schedule;
+mod screenpad;
+pub mod state;
+#[cfg(test)]
+mod testutil;
+pub mod touch;
+pub mod watch;
+
+pub use backend::ScreenpadBackend;
+pub use command::{Action, Outcome};
+pub use config::Config;
+pub use curve::Curve;
+pub use error::{Result, ScreenpadError};
+pub use fade::Fade;
+pub use level::Level;
+pub use screenpad::{BrightnessChange, CycleStep, ScreenState, Screenpad, Transition};
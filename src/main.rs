@@ -1,259 +1,445 @@
-use serde_derive::{Deserialize, Serialize};
-use std::env;
-use std::fs;
+mod cli;
+mod status;
+
+use clap::Parser;
+use cli::{Cli, Command, Increment, ScheduleCommand, StatusFormat};
+use screenpadctl::als::{self, Sensor};
+use screenpadctl::config::{self, Config};
+use screenpadctl::daemon::{self, Tasks};
+use screenpadctl::discovery::{self, DEFAULT_SYSFS_ROOT};
+use screenpadctl::fade::FadeToken;
+use screenpadctl::follow::Follower;
+use screenpadctl::schedule::{Daylight, LocalTime, Schedule, ScheduleMode, TimeOfDay};
+use screenpadctl::{idle, power, schedule};
+use screenpadctl::{Action, Outcome, Result, Screenpad, ScreenpadError};
+use serde_json::json;
+use std::error::Error;
 use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
 
-#[derive(PartialEq)]
-enum ScreenState {
-    On,
-    Off,
-    Dim,
+/// How results are shown, set by `--quiet` and `--json`
+struct Output {
+    quiet: bool,
+    json: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Config {
-    positive_increment: i16,
-    negative_increment: i16,
-}
+impl Output {
+    fn error(&self, err: &ScreenpadError) {
+        // print an error with its causes
+        let mut text = err.to_string();
+        let mut source = err.source();
+        while let Some(cause) = source {
+            text = format!("{}: {}", text, cause);
+            source = cause.source();
+        }
 
-impl ::std::default::Default for Config {
-    fn default() -> Self {
-        Self {
-            positive_increment: 15,
-            negative_increment: -15,
+        if self.json {
+            println!(
+                "{}",
+                json!({ "success": false, "error": text, "exit_code": err.exit_code() })
+            );
+        } else {
+            eprintln!("\x1b[91mError: {}\x1b[0m", text);
         }
     }
-}
 
-fn print_error(text: &str) {
-    println!("\x1b[91mError: {}\x1b[0m", text);
-}
+    /// Report a finished action along with the resulting device state
+    fn outcome(&self, outcome: &Outcome) {
+        if self.json {
+            let mut value = serde_json::to_value(outcome).expect("outcomes always serialize");
+            value["success"] = true.into();
+            println!("{}", value);
+        } else if outcome.action == "get" {
+            println!("{}", outcome.message);
+        } else if outcome.interrupted || (!outcome.changed && outcome.action != "cycle") {
+            self.warn(&outcome.message);
+        } else if !self.quiet {
+            println!("\x1b[92mSuccess: {}\x1b[0m", outcome.message);
+        }
+    }
 
-fn print_success(text: &str) {
-    println!("\x1b[92mSuccess: {}\x1b[0m", text);
+    /// Print a warning that does not make the command fail
+    fn warn(&self, text: &str) {
+        if !self.json && !self.quiet {
+            eprintln!("\x1b[93mWarning: {}\x1b[0m", text);
+        }
+    }
 }
 
-const BRIGHTNESS_CTRL_FILE: &str = "/sys/class/leds/asus::screenpad/brightness";
-
-fn get_brightness() -> i16 {
-    let mut brightness_string =
-        fs::read_to_string(BRIGHTNESS_CTRL_FILE).expect("Cannot open control file");
+fn show_config(out: &Output, cfg: &Config) {
+    if out.json {
+        println!("{}", json!({ "success": true, "config": cfg }));
+    } else {
+        print!(
+            "{}",
+            toml::to_string_pretty(cfg).expect("config always serializes")
+        );
+    }
+}
 
-    if brightness_string.ends_with('\n') {
-        brightness_string.pop();
+/// Print the brightness every `step` minutes of today
+fn preview_schedule(out: &Output, screenpad: &Screenpad, cfg: &Config, step: u16) -> Result<()> {
+    let today = LocalTime::now();
+    let schedule = Schedule::for_day(&cfg.schedule, &today)?;
+    let max = screenpad.max_brightness()?;
+    let curve = screenpad.curve();
+
+    let daylight = match cfg.schedule.mode {
+        ScheduleMode::Sun => {
+            let (latitude, longitude) = cfg.schedule.position()?;
+            Some(Daylight::compute(latitude, longitude, &today))
+        }
+        ScheduleMode::Fixed => None,
+    };
+    let mut points = Vec::new();
+    for minute in (0..1440).step_by(step as usize) {
+        let brightness = schedule.brightness_at(minute as f64, max, &curve)?;
+        let percent = (curve.perceived(brightness, max) * 100.0).round();
+        points.push((TimeOfDay::from_minutes(minute as f64), brightness, percent));
     }
 
-    brightness_string
-        .parse::<i16>()
-        .expect("Cannot convert string to int")
-}
+    if out.json {
+        let (sunrise, sunset) = match daylight {
+            Some(Daylight::Sun { sunrise, sunset }) => (
+                Some(TimeOfDay::from_minutes(sunrise).to_string()),
+                Some(TimeOfDay::from_minutes(sunset).to_string()),
+            ),
+            _ => (None, None),
+        };
+        let points: Vec<_> = points
+            .iter()
+            .map(|(time, brightness, percent)| {
+                json!({ "time": time.to_string(), "brightness": brightness, "percent": percent })
+            })
+            .collect();
+        println!(
+            "{}",
+            json!({ "success": true, "sunrise": sunrise, "sunset": sunset, "points": points })
+        );
+        return Ok(());
+    }
 
-/// Overwite brightness
-fn overwrite_brightness(value: i16) {
-    fs::write(BRIGHTNESS_CTRL_FILE, value.to_string()).expect("Cannot write new value to file");
+    match daylight {
+        Some(Daylight::Sun { sunrise, sunset }) => println!(
+            "Sunrise {}, sunset {}",
+            TimeOfDay::from_minutes(sunrise),
+            TimeOfDay::from_minutes(sunset)
+        ),
+        Some(Daylight::PolarDay) => println!("The sun does not set today"),
+        Some(Daylight::PolarNight) => println!("The sun does not rise today"),
+        None => {}
+    }
+    for (time, brightness, percent) in points {
+        let bar = "#".repeat((percent / 5.0) as usize);
+        println!("{}  {:>5}  {:>3}%  {}", time, brightness, percent, bar);
+    }
+    Ok(())
 }
 
-/// increment brightness by +/-ve value
-fn increment_brightness(value: i16) {
-    let current_brightness = get_brightness();
+/// Print the state for a status bar, with `follow` again on every change
+fn show_status(
+    out: &Output,
+    screenpad: &Screenpad,
+    cfg: &Config,
+    format: StatusFormat,
+    follow: bool,
+    interval: Duration,
+) -> Result<()> {
+    let watcher = screenpad.watcher();
+    let mut last = None;
+    loop {
+        let outcome = screenpad.outcome("status", false, String::new())?;
+        let line = status::render(format, &outcome, &cfg.status, follow);
+
+        if last.as_ref() != Some(&line) {
+            if out.json {
+                out.outcome(&Outcome {
+                    message: status::render(StatusFormat::Plain, &outcome, &cfg.status, follow),
+                    ..outcome
+                });
+            } else {
+                println!("{}", line);
+            }
+            last = Some(line);
+        }
 
-    if (current_brightness + value) <= 255 && (current_brightness + value) >= 0 {
-        overwrite_brightness(current_brightness + value);
+        if !follow {
+            return Ok(());
+        }
+        watcher.wait(interval);
     }
 }
 
-const BRIGHTNESS_BACKUP_FILE: &str = "~/.local/share/brightness_backup";
-
-/// Store current brightness in file
-fn backup_brightness() {
-    let current_brightness = get_brightness();
+/// Print an event whenever the brightness or state changes, whoever changed
+/// it. One JSON object per line with `--json`
+fn watch_changes(out: &Output, screenpad: &Screenpad, interval: Duration) -> Result<()> {
+    let watcher = screenpad.watcher();
+    let mut last = screenpad.outcome("watch", false, String::new())?;
 
-    if !Path::new(BRIGHTNESS_BACKUP_FILE).exists() {
-        fs::File::create(BRIGHTNESS_BACKUP_FILE).expect("Cannot create backup file");
-    }
+    loop {
+        watcher.wait(interval);
 
-    fs::write(BRIGHTNESS_BACKUP_FILE, current_brightness.to_string())
-        .expect("Cannot write to backup file");
-}
+        let current = screenpad.outcome("watch", true, String::new())?;
+        if current.brightness == last.brightness && current.state == last.state {
+            continue;
+        }
 
-/// restore previous brightness value
-fn restore_brightness() -> i16 {
-    let mut prev_brightness =
-        fs::read_to_string(BRIGHTNESS_BACKUP_FILE).expect("Cannot open backup file");
+        let mut message = format!("Brightness {} -> {}", last.brightness, current.brightness);
+        if current.state != last.state {
+            message = format!("{}, screen {} -> {}", message, last.state, current.state);
+        }
 
-    if prev_brightness.ends_with('\n') {
-        prev_brightness.pop();
+        if out.json {
+            let mut value = serde_json::to_value(Outcome {
+                message,
+                ..current.clone()
+            })
+            .expect("outcomes always serialize");
+            value["success"] = true.into();
+            value["previous_brightness"] = last.brightness.into();
+            value["previous_state"] = last.state.into();
+            println!("{}", value);
+        } else {
+            println!("{}", message);
+        }
+        last = current;
     }
-
-    prev_brightness
-        .parse::<i16>()
-        .expect("Cannot convert string to int")
 }
 
-/// Get current state of display
-/// 0 -> off
-/// 1 -> on
-/// 2 -> dim
-fn screen_state() -> ScreenState {
-    let current_brightness = get_brightness();
-
-    return match current_brightness {
-        0 => ScreenState::Off,
-        1 => ScreenState::Dim,
-        _ => ScreenState::On,
-    };
+/// Command line subcommand as device action
+fn action(command: &Command) -> Option<Action> {
+    Some(match *command {
+        Command::Get => Action::Get,
+        Command::Set { value } => Action::Set(value),
+        Command::Up => Action::Up,
+        Command::Down => Action::Down,
+        Command::On => Action::On,
+        Command::Off => Action::Off,
+        Command::Dim => Action::Dim,
+        Command::Toggle => Action::Toggle,
+        Command::Cycle => Action::Cycle,
+        Command::Preset { ref name } => Action::Preset(name.clone()),
+        _ => return None,
+    })
 }
 
-fn main() {
-    let mut cfg: Config = confy::load("screenpadctl", None).expect("Cannot Create Config File");
-
-    let args: Vec<String> = env::args().collect();
+/// Run `action` on the LED `name`. LEDs are not owned by the daemon, so
+/// this always runs in process
+fn run_led(cli: &Cli, out: &Output, cfg: &Config, name: &str, action: Action) -> Result<()> {
+    let cfg = cfg.for_led(name);
+    let mut led = Screenpad::open_led(&cli.sysfs_root, name, &cfg.led(name))?;
+    led.apply_config(&cfg);
 
-    if args.len() < 2 {
-        print_error("Specify argument\nuse `help` for usage details");
-        return;
+    if action.writes() {
+        let token = FadeToken::claim_led(name)?;
+        led = led.with_interrupt(move || !token.is_current());
     }
+    out.outcome(&led.execute(action, &cfg)?);
+    Ok(())
+}
 
-    let current_state = screen_state();
+/// Whether the command line picks its own device, which may not be the one
+/// a daemon owns
+fn picks_device(cli: &Cli) -> bool {
+    cli.device.is_some()
+        || cli.sysfs_root != Path::new(DEFAULT_SYSFS_ROOT)
+        || discovery::mock_requested()
+}
 
-    match args[1].as_str() {
-        "b" => println!("Current Brightness is {}", get_brightness()),
+fn open(cli: &Cli, cfg: &Config) -> Result<Screenpad> {
+    let mut screenpad = Screenpad::open(&cli.sysfs_root, cli.device.as_deref())?;
+    screenpad.apply_config(cfg);
+    Ok(screenpad)
+}
 
-        "bup" => {
-            increment_brightness(cfg.positive_increment);
-            print_success("Brightness up");
+/// Start the background features enabled in the config
+fn spawn_features(sysfs_root: &Path, tasks: &Tasks, cfg: &Config) {
+    if cfg.follow.enabled {
+        match Follower::new(sysfs_root, &cfg.follow) {
+            Ok(follower) => follower.spawn(tasks),
+            Err(err) => eprintln!("follow: {}", err),
         }
-        "bdown" => {
-            increment_brightness(cfg.negative_increment);
-            print_success("Brightness down");
+    }
+    if cfg.als.enabled {
+        match Sensor::find(sysfs_root, cfg.als.sensor.as_deref()) {
+            Ok(sensor) => als::spawn(tasks, sensor, &cfg.als),
+            Err(err) => eprintln!("als: {}", err),
         }
-        "bconfig" => {
-            if args.len() <= 2 {
-                print_error("Specify which increment value to change. Use [pos/neg] <value>");
-                return;
-            }
-
-            if args.len() <= 3 {
-                print_error("Specify increment value");
-                return;
-            }
+    }
+    if cfg.idle.enabled {
+        match idle::open_source(&cfg.idle) {
+            Ok(source) => idle::spawn(tasks, source, &cfg.idle),
+            Err(err) => eprintln!("idle: {}", err),
+        }
+    }
+    if cfg.power.enabled {
+        power::spawn(tasks, sysfs_root, &cfg.power);
+    }
+    if cfg.schedule.enabled {
+        schedule::spawn(tasks, &cfg.schedule);
+    }
+}
 
-            let value = args[3]
-                .parse::<i16>()
-                .expect("Cannot convert string to int");
-            let operation = &args[2];
-
-            match operation.as_str() {
-                "pos" => cfg.positive_increment = value,
-                "neg" => cfg.negative_increment = value,
-                _ => {
-                    print_error("Enter valid increment value to change");
-                    return;
-                }
-            }
+fn run(cli: Cli, out: &Output) -> Result<()> {
+    let mut cfg = config::load()?;
+    let socket = daemon::socket_path();
 
-            let _ = confy::store("screenpadctl", None, cfg);
-            print_success(format!("Set {} increment to {}", operation, value).as_str());
+    if let Some(action) = action(&cli.command) {
+        if let Some(name) = &cli.led {
+            return run_led(&cli, out, &cfg, name, action);
         }
-        "bset" => {
-            if args.len() <= 2 {
-                print_error(
-                    "Specifiy int between [0->255] inclusive to set the brightness manually",
-                );
-                return;
-            }
-
-            let value = match args[2].parse::<i16>() {
-                Ok(value) => value,
-                Err(_) => {
-                    print_error("Enter a valid int between [0->255] inclusive");
-                    return;
-                }
-            };
 
-            if value > 255 || value < 0 {
-                print_error("Int out of range. Brightness is between [0->255] inclusive");
-                return;
+        // let a running daemon do it, so requests are serialized
+        if !picks_device(&cli) {
+            let timeout = daemon::reply_timeout(&cfg.fade);
+            if let Some(reply) = daemon::request(&socket, &action.to_string(), timeout)? {
+                out.outcome(&reply.into_result()?);
+                return Ok(());
             }
-
-            overwrite_brightness(value);
-            print_success(format!("Set brightness to {}", value).as_str());
         }
 
-        "on" => {
-            if current_state == ScreenState::On {
-                print_error("Screen is already on");
-                return;
-            }
-            overwrite_brightness(restore_brightness());
-            print_success("Screen on");
+        // a new write interrupts a fade still running from an earlier one
+        let mut screenpad = open(&cli, &cfg)?;
+        if action.writes() {
+            let token = FadeToken::claim()?;
+            screenpad = screenpad.with_interrupt(move || !token.is_current());
         }
+        out.outcome(&screenpad.execute(action, &cfg)?);
+        return Ok(());
+    }
 
-        "off" => {
-            if current_state == ScreenState::Off {
-                print_error("Screen is already off");
-                return;
-            }
-            backup_brightness();
-            overwrite_brightness(0);
-            print_success("Screen off");
+    if cli.led.is_some() && !matches!(cli.command, Command::Config { .. }) {
+        return Err(ScreenpadError::Usage(
+            "--led only works with device actions and config".to_string(),
+        ));
+    }
+
+    match cli.command {
+        Command::Status {
+            format,
+            follow,
+            interval_ms,
+        } => show_status(
+            out,
+            &open(&cli, &cfg)?,
+            &cfg,
+            format,
+            follow,
+            Duration::from_millis(interval_ms.max(1)),
+        ),
+        Command::Watch { interval_ms } => watch_changes(
+            out,
+            &open(&cli, &cfg)?,
+            Duration::from_millis(interval_ms.max(1)),
+        ),
+        Command::Daemon => {
+            let sysfs_root = cli.sysfs_root.clone();
+            daemon::serve(&socket, open(&cli, &cfg)?, cfg, |tasks, cfg| {
+                spawn_features(&sysfs_root, tasks, cfg)
+            })
         }
-        "toggle" => {
-            if current_state == ScreenState::On {
-                backup_brightness();
-                overwrite_brightness(0);
-                print_success("Toggle screen off");
-            } else if current_state == ScreenState::Off {
-                overwrite_brightness(restore_brightness());
-                print_success("Toggle screen on");
-            }
+        Command::Follow => {
+            let follower = Follower::new(&cli.sysfs_root, &cfg.follow)?;
+            follower.run(&open(&cli, &cfg)?)
         }
-        "dim" => {
-            if current_state == ScreenState::Dim {
-                return;
-            }
+        Command::Auto => {
+            let sensor = Sensor::find(&cli.sysfs_root, cfg.als.sensor.as_deref())?;
+            als::run(&open(&cli, &cfg)?, &sensor, &cfg.als)
+        }
+        Command::Schedule {
+            command: ScheduleCommand::Preview { step },
+        } => preview_schedule(out, &open(&cli, &cfg)?, &cfg, step),
 
-            if current_state == ScreenState::On {
-                backup_brightness();
-            }
+        #[cfg(feature = "dbus")]
+        Command::Dbus => screenpadctl::dbus::serve(open(&cli, &cfg)?, cfg),
 
-            overwrite_brightness(1);
-            print_success("Dim Screen");
-        }
-        "cycle" => {
-            // on -> dim -> off
-            match current_state {
-                // on to dim
-                ScreenState::On => {
-                    backup_brightness();
-                    overwrite_brightness(1);
-                    print_success("Cycle on -> dim");
+        Command::Config { increment, value } => {
+            let (Some(increment), Some(value)) = (increment, value) else {
+                show_config(out, &cfg);
+                return Ok(());
+            };
+
+            let (positive, negative) = match &cli.led {
+                Some(led) => {
+                    let led = cfg.leds.entry(led.clone()).or_default();
+                    (&mut led.positive_increment, &mut led.negative_increment)
                 }
-                // dim to off
-                ScreenState::Dim => {
-                    overwrite_brightness(0);
-                    print_success("Cycle dim -> off");
+                None => (&mut cfg.positive_increment, &mut cfg.negative_increment),
+            };
+            let name = match increment {
+                Increment::Pos => {
+                    *positive = value;
+                    "pos"
                 }
-                // off to on
-                ScreenState::Off => {
-                    overwrite_brightness(restore_brightness());
-                    print_success("Cycle off -> on");
+                Increment::Neg => {
+                    *negative = value;
+                    "neg"
                 }
+            };
+
+            config::store(&cfg)?;
+            // a running daemon keeps the old config otherwise
+            if let Some(reply) =
+                daemon::request(&socket, "reload", daemon::reply_timeout(&cfg.fade))?
+            {
+                reply.into_result()?;
+            }
+
+            if out.json {
+                show_config(out, &cfg);
+            } else if !out.quiet {
+                let target = match &cli.led {
+                    Some(led) => format!(" of {}", led),
+                    None => String::new(),
+                };
+                println!(
+                    "\x1b[92mSuccess: Set {} increment{} to {}\x1b[0m",
+                    name, target, value
+                );
             }
+            Ok(())
         }
 
-        "help" => println!(
-            "Usage details:
-        Print current brightness: `b`
-        Config brightness increment: `bconfig [pos/neg] <value>`
-        Brightness control: `bup`, `bdown`, `bset <value>`
-        Power control: `on`, `off`, `dim`
-        Special power control modes: 
-            `toggle`: toggle between on and off
-            `cycle`: cycle between [on -> dim -> off] (loops)
-"
-        ),
+        _ => unreachable!("device actions are handled above"),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // scripts asking for JSON get it for bad arguments too
+        Err(err) if err.use_stderr() && std::env::args().any(|arg| arg == "--json") => {
+            let text = err.render().to_string();
+            // the first paragraph without the usage hint
+            let message: Vec<&str> = text
+                .split("\n\n")
+                .next()
+                .unwrap_or_default()
+                .lines()
+                .map(str::trim)
+                .collect();
+            let message = message.join(" ");
+            let err = ScreenpadError::Usage(message.trim_start_matches("error: ").to_string());
+            Output {
+                quiet: false,
+                json: true,
+            }
+            .error(&err);
+            return ExitCode::from(err.exit_code());
+        }
+        Err(err) => err.exit(),
+    };
+    let out = Output {
+        quiet: cli.quiet,
+        json: cli.json,
+    };
 
-        _ => print_error("Invalid Argument\nUse `help` command"),
+    match run(cli, &out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            out.error(&err);
+            ExitCode::from(err.exit_code())
+        }
     }
 }
@@ -0,0 +1,372 @@
+use crate::backend::read_int;
+use crate::daemon::Tasks;
+use crate::discovery::class_entries;
+use crate::error::Result;
+use crate::level::Level;
+use crate::screenpad::{ScreenState, Screenpad};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// What to do with the screenpad on one power source
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default)]
+pub struct PowerProfile {
+    /// Brightness while on, e.g. `"40%"`. The brightness from before any
+    /// profile changed it when unset
+    pub brightness: Option<Level>,
+    /// Turn the screenpad off
+    pub off: bool,
+}
+
+/// Settings for switching profiles with the power source
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PowerConfig {
+    /// Switch inside `screenpadctl daemon`
+    pub enabled: bool,
+    /// Battery percentage below which the screenpad is turned off, 0 never
+    pub off_below: u8,
+    pub poll_ms: u64,
+    pub ac: PowerProfile,
+    pub battery: PowerProfile,
+}
+
+impl Default for PowerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            off_below: 10,
+            poll_ms: 5000,
+            ac: PowerProfile::default(),
+            battery: PowerProfile {
+                brightness: Some(Level::Fraction(0.4)),
+                off: false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerState {
+    Ac,
+    Battery,
+    /// On battery below the threshold
+    Low,
+}
+
+/// Snapshot of `<sysfs_root>/class/power_supply`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerStatus {
+    /// Whether a mains or USB supply is plugged in
+    pub online: bool,
+    /// Lowest charge of the system batteries in percent
+    pub capacity: Option<u8>,
+}
+
+impl PowerStatus {
+    pub fn read(sysfs_root: &Path) -> Result<Self> {
+        let mut status = Self {
+            online: false,
+            capacity: None,
+        };
+        let mut batteries = false;
+
+        for supply in class_entries(&sysfs_root.join("class").join("power_supply")) {
+            let attribute = |name| fs::read_to_string(supply.join(name)).unwrap_or_default();
+            // batteries of mice and keyboards say nothing about the laptop
+            if attribute("scope").trim() == "Device" {
+                continue;
+            }
+
+            if attribute("type").trim() == "Battery" {
+                batteries = true;
+                if supply.join("capacity").exists() {
+                    let capacity: u8 = read_int(&supply.join("capacity"))?;
+                    status.capacity = Some(status.capacity.map_or(capacity, |c| c.min(capacity)));
+                }
+            } else if supply.join("online").exists() {
+                status.online |= read_int::<u8>(&supply.join("online"))? > 0;
+            }
+        }
+
+        // without a battery the machine runs on mains
+        status.online |= !batteries;
+        Ok(status)
+    }
+
+    pub fn state(&self, off_below: u8) -> PowerState {
+        match self.capacity {
+            _ if self.online => PowerState::Ac,
+            Some(capacity) if capacity < off_below => PowerState::Low,
+            _ => PowerState::Battery,
+        }
+    }
+}
+
+/// What profiles changed, to undo it on the next switch
+#[derive(Default)]
+struct Changes {
+    /// Brightness before a profile set one
+    saved: Option<i16>,
+    /// The screenpad was turned off by a profile
+    turned_off: bool,
+}
+
+/// Applies power profiles to the screenpad
+#[derive(Clone)]
+pub struct PowerProfiles {
+    ac: PowerProfile,
+    battery: PowerProfile,
+    changes: Arc<Mutex<Changes>>,
+}
+
+impl PowerProfiles {
+    pub fn new(cfg: &PowerConfig) -> Self {
+        Self {
+            ac: cfg.ac,
+            battery: cfg.battery,
+            changes: Arc::default(),
+        }
+    }
+
+    pub fn profile(&self, state: PowerState) -> PowerProfile {
+        match state {
+            PowerState::Ac => self.ac,
+            PowerState::Battery => self.battery,
+            PowerState::Low => PowerProfile {
+                brightness: None,
+                off: true,
+            },
+        }
+    }
+
+    /// Switch to the profile of `state`. Returns the new brightness
+    pub fn apply(&self, screenpad: &Screenpad, state: PowerState) -> Result<i16> {
+        let profile = self.profile(state);
+        let mut changes = self.changes.lock().expect("power lock poisoned");
+
+        if profile.off {
+            if screenpad.screen_state()? != ScreenState::Off {
+                screenpad.off()?;
+                changes.turned_off = true;
+            }
+            return screenpad.get_brightness();
+        }
+        if changes.turned_off {
+            // on restores the brightness backed up when turning off
+            screenpad.on()?;
+            changes.turned_off = false;
+        }
+
+        // a screenpad dimmed or turned off by hand stays that way
+        if screenpad.screen_state()? == ScreenState::On {
+            match profile.brightness {
+                Some(level) => {
+                    if changes.saved.is_none() {
+                        changes.saved = Some(screenpad.get_brightness()?);
+                    }
+                    screenpad.set_level(level)?;
+                }
+                None => {
+                    if let Some(saved) = changes.saved.take() {
+                        screenpad.overwrite_brightness(saved)?;
+                    }
+                }
+            }
+        }
+        screenpad.get_brightness()
+    }
+}
+
+/// Watches the power supplies for switches
+pub struct PowerMonitor {
+    root: PathBuf,
+    off_below: u8,
+    state: Option<PowerState>,
+}
+
+impl PowerMonitor {
+    pub fn new(sysfs_root: &Path, cfg: &PowerConfig) -> Self {
+        Self {
+            root: sysfs_root.to_path_buf(),
+            off_below: cfg.off_below,
+            state: None,
+        }
+    }
+
+    /// Returns the power state when it differs from the last check
+    pub fn update(&mut self) -> Result<Option<PowerState>> {
+        let state = PowerStatus::read(&self.root)?.state(self.off_below);
+        if self.state == Some(state) {
+            return Ok(None);
+        }
+        self.state = Some(state);
+        Ok(Some(state))
+    }
+}
+
+/// Switch profiles in the background of the daemon
+pub fn spawn(tasks: &Tasks, sysfs_root: &Path, cfg: &PowerConfig) {
+    let tasks = tasks.clone();
+    let mut monitor = PowerMonitor::new(sysfs_root, cfg);
+    let profiles = PowerProfiles::new(cfg);
+    let poll = Duration::from_millis(cfg.poll_ms.max(1));
+
+    thread::spawn(move || loop {
+        match monitor.update() {
+            Ok(Some(state)) => {
+                let profiles = profiles.clone();
+                let submitted = tasks.submit(move |screenpad, _| {
+                    if let Err(err) = profiles.apply(screenpad, state) {
+                        eprintln!("power: {}", err);
+                    }
+                });
+                if !submitted {
+                    return;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("power: {}", err),
+        }
+        thread::sleep(poll);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+
+    fn supply(dir: &TempDir, name: &str, attributes: &[(&str, &str)]) {
+        for (attribute, value) in attributes {
+            dir.write(
+                &format!("class/power_supply/{}/{}", name, attribute),
+                &format!("{}\n", value),
+            );
+        }
+    }
+
+    #[test]
+    fn reads_mains_and_battery() {
+        let dir = TempDir::new();
+        supply(&dir, "AC0", &[("type", "Mains"), ("online", "0")]);
+        supply(&dir, "BAT0", &[("type", "Battery"), ("capacity", "57")]);
+
+        let status = PowerStatus::read(dir.path()).unwrap();
+        assert_eq!(
+            status,
+            PowerStatus {
+                online: false,
+                capacity: Some(57)
+            }
+        );
+        assert_eq!(status.state(10), PowerState::Battery);
+        assert_eq!(status.state(60), PowerState::Low);
+
+        supply(&dir, "AC0", &[("online", "1")]);
+        assert_eq!(
+            PowerStatus::read(dir.path()).unwrap().state(60),
+            PowerState::Ac
+        );
+    }
+
+    #[test]
+    fn skips_device_batteries() {
+        let dir = TempDir::new();
+        supply(&dir, "AC0", &[("type", "Mains"), ("online", "0")]);
+        supply(&dir, "BAT0", &[("type", "Battery"), ("capacity", "80")]);
+        supply(
+            &dir,
+            "hidpp_battery_0",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "5")],
+        );
+
+        let status = PowerStatus::read(dir.path()).unwrap();
+        assert_eq!(status.capacity, Some(80));
+        assert_eq!(status.state(10), PowerState::Battery);
+    }
+
+    #[test]
+    fn takes_the_lowest_of_several_batteries() {
+        let dir = TempDir::new();
+        supply(&dir, "BAT0", &[("type", "Battery"), ("capacity", "64")]);
+        supply(&dir, "BAT1", &[("type", "Battery"), ("capacity", "8")]);
+
+        let status = PowerStatus::read(dir.path()).unwrap();
+        assert_eq!(
+            status,
+            PowerStatus {
+                online: false,
+                capacity: Some(8)
+            }
+        );
+        assert_eq!(status.state(10), PowerState::Low);
+    }
+
+    #[test]
+    fn no_battery_means_mains() {
+        let dir = TempDir::new();
+        assert_eq!(
+            PowerStatus::read(dir.path()).unwrap().state(10),
+            PowerState::Ac
+        );
+
+        supply(&dir, "AC0", &[("type", "Mains"), ("online", "0")]);
+        supply(
+            &dir,
+            "hid-mouse-battery",
+            &[("type", "Battery"), ("scope", "Device"), ("capacity", "3")],
+        );
+        assert_eq!(
+            PowerStatus::read(dir.path()).unwrap().state(10),
+            PowerState::Ac
+        );
+    }
+
+    #[test]
+    fn monitor_reports_switches_once() {
+        let dir = TempDir::new();
+        supply(&dir, "AC0", &[("type", "Mains"), ("online", "1")]);
+        supply(&dir, "BAT0", &[("type", "Battery"), ("capacity", "50")]);
+        let mut monitor = PowerMonitor::new(dir.path(), &PowerConfig::default());
+
+        assert_eq!(monitor.update().unwrap(), Some(PowerState::Ac));
+        assert_eq!(monitor.update().unwrap(), None);
+
+        supply(&dir, "AC0", &[("online", "0")]);
+        assert_eq!(monitor.update().unwrap(), Some(PowerState::Battery));
+        assert_eq!(monitor.update().unwrap(), None);
+    }
+
+    #[test]
+    fn profiles_round_trip_restores_the_brightness() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+        let profiles = PowerProfiles::new(&PowerConfig::default());
+
+        assert_eq!(
+            profiles.apply(&screenpad, PowerState::Battery).unwrap(),
+            102
+        );
+        assert_eq!(profiles.apply(&screenpad, PowerState::Low).unwrap(), 0);
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::Off);
+        assert_eq!(profiles.apply(&screenpad, PowerState::Ac).unwrap(), 200);
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::On);
+    }
+
+    #[test]
+    fn profiles_leave_a_screen_turned_off_by_hand() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 200, 255);
+        let profiles = PowerProfiles::new(&PowerConfig::default());
+
+        screenpad.off().unwrap();
+        assert_eq!(profiles.apply(&screenpad, PowerState::Battery).unwrap(), 0);
+        assert_eq!(profiles.apply(&screenpad, PowerState::Low).unwrap(), 0);
+        assert_eq!(profiles.apply(&screenpad, PowerState::Ac).unwrap(), 0);
+    }
+}
@@ -0,0 +1,535 @@
+use crate::curve::Curve;
+use crate::daemon::Tasks;
+use crate::error::{Result, ScreenpadError};
+use crate::level::Level;
+use crate::screenpad::Screenpad;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const MINUTES_PER_DAY: f64 = 1440.0;
+
+/// Zenith of sunrise and sunset, the sun's radius and refraction included
+const SUN_ZENITH: f64 = 90.833;
+
+/// Time of day in minutes after midnight, written `HH:MM`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeOfDay(u16);
+
+impl TimeOfDay {
+    pub fn minutes(&self) -> u16 {
+        self.0
+    }
+
+    /// `minutes` after midnight, wrapped into one day
+    pub fn from_minutes(minutes: f64) -> Self {
+        Self(minutes.rem_euclid(MINUTES_PER_DAY).floor() as u16)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = ScreenpadError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = || ScreenpadError::Usage(format!("Invalid time `{}`, use HH:MM", text));
+
+        let (hours, minutes) = text.split_once(':').ok_or_else(invalid)?;
+        let hours: u16 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self(hours * 60 + minutes))
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = ScreenpadError;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(time: TimeOfDay) -> Self {
+        time.to_string()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleMode {
+    /// The configured `points`
+    #[default]
+    Fixed,
+    /// `day` between sunrise and sunset, `night` otherwise
+    Sun,
+}
+
+/// Settings for brightness by time of day
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// Follow the schedule inside `screenpadctl daemon`
+    pub enabled: bool,
+    pub mode: ScheduleMode,
+    /// Degrees north, required for `sun`
+    pub latitude: Option<f64>,
+    /// Degrees east, required for `sun`
+    pub longitude: Option<f64>,
+    pub day: Level,
+    pub night: Level,
+    /// Minutes the change at sunrise and sunset takes
+    pub transition_min: u16,
+    pub poll_ms: u64,
+    /// Brightness by local time for `fixed`, interpolated in between
+    pub points: BTreeMap<TimeOfDay, Level>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ScheduleMode::Fixed,
+            latitude: None,
+            longitude: None,
+            day: Level::Fraction(0.8),
+            night: Level::Fraction(0.3),
+            transition_min: 60,
+            poll_ms: 60_000,
+            points: BTreeMap::from([
+                (TimeOfDay(7 * 60), Level::Fraction(0.8)),
+                (TimeOfDay(20 * 60), Level::Fraction(0.3)),
+            ]),
+        }
+    }
+}
+
+impl ScheduleConfig {
+    /// Fails on `sun` without a position, which would run on the times of
+    /// the equator
+    pub fn validate(&self) -> Result<()> {
+        match self.mode {
+            ScheduleMode::Sun => self.position().map(|_| ()),
+            ScheduleMode::Fixed => Ok(()),
+        }
+    }
+
+    /// Latitude and longitude for `sun`
+    pub fn position(&self) -> Result<(f64, f64)> {
+        let (Some(latitude), Some(longitude)) = (self.latitude, self.longitude) else {
+            return Err(ScreenpadError::InvalidConfig(
+                "The sun schedule needs a latitude and longitude".to_string(),
+            ));
+        };
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(ScreenpadError::InvalidConfig(format!(
+                "Position {}, {} is not a latitude and longitude",
+                latitude, longitude
+            )));
+        }
+        Ok((latitude, longitude))
+    }
+}
+
+/// Local date and time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    /// 1 for January 1st
+    pub day_of_year: u16,
+    /// Minutes since local midnight
+    pub minute: f64,
+    /// Minutes local time is ahead of UTC
+    pub utc_offset: f64,
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        // SAFETY: `time` accepts a null pointer and `localtime_r` only writes
+        // to the zeroed tm it is given
+        let tm = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            tm
+        };
+
+        Self {
+            day_of_year: tm.tm_yday as u16 + 1,
+            minute: (tm.tm_hour * 60 + tm.tm_min) as f64 + tm.tm_sec as f64 / 60.0,
+            utc_offset: tm.tm_gmtoff as f64 / 60.0,
+        }
+    }
+}
+
+/// Sunrise and sunset of one day, in local minutes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Daylight {
+    Sun { sunrise: f64, sunset: f64 },
+    PolarDay,
+    PolarNight,
+}
+
+impl Daylight {
+    /// Computed offline with the NOAA approximation, good to a few minutes
+    pub fn compute(latitude: f64, longitude: f64, date: &LocalTime) -> Self {
+        let gamma = 2.0 * PI / 365.0 * (date.day_of_year as f64 - 1.0);
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * gamma.cos()
+                - 0.032077 * gamma.sin()
+                - 0.014615 * (2.0 * gamma).cos()
+                - 0.040849 * (2.0 * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+            - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin()
+            - 0.002697 * (3.0 * gamma).cos()
+            + 0.00148 * (3.0 * gamma).sin();
+
+        let latitude = latitude.clamp(-90.0, 90.0).to_radians();
+        let cos_hour_angle = SUN_ZENITH.to_radians().cos() / (latitude.cos() * declination.cos())
+            - latitude.tan() * declination.tan();
+        if cos_hour_angle > 1.0 {
+            return Daylight::PolarNight;
+        }
+        if cos_hour_angle < -1.0 {
+            return Daylight::PolarDay;
+        }
+
+        let hour_angle = cos_hour_angle.acos().to_degrees();
+        let noon = 720.0 - 4.0 * longitude - equation_of_time + date.utc_offset;
+        Daylight::Sun {
+            sunrise: (noon - 4.0 * hour_angle).rem_euclid(MINUTES_PER_DAY),
+            sunset: (noon + 4.0 * hour_angle).rem_euclid(MINUTES_PER_DAY),
+        }
+    }
+}
+
+/// Brightness points of one day
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// Sorted by minute
+    points: Vec<(f64, Level)>,
+}
+
+impl Schedule {
+    /// The schedule of `cfg` on the day of `date`
+    pub fn for_day(cfg: &ScheduleConfig, date: &LocalTime) -> Result<Self> {
+        let mut points: Vec<(f64, Level)> = match cfg.mode {
+            ScheduleMode::Fixed => cfg
+                .points
+                .iter()
+                .map(|(time, level)| (time.minutes() as f64, *level))
+                .collect(),
+            ScheduleMode::Sun => {
+                let (latitude, longitude) = cfg.position()?;
+                let transition = cfg.transition_min as f64;
+                match Daylight::compute(latitude, longitude, date) {
+                    Daylight::Sun { sunrise, sunset } => vec![
+                        (sunrise - transition / 2.0, cfg.night),
+                        (sunrise + transition / 2.0, cfg.day),
+                        (sunset - transition / 2.0, cfg.day),
+                        (sunset + transition / 2.0, cfg.night),
+                    ],
+                    Daylight::PolarDay => vec![(0.0, cfg.day)],
+                    Daylight::PolarNight => vec![(0.0, cfg.night)],
+                }
+            }
+        };
+
+        if points.is_empty() {
+            return Err(ScreenpadError::Usage(
+                "The schedule has no points".to_string(),
+            ));
+        }
+        if let Some((_, level)) = points
+            .iter()
+            .find(|(_, level)| matches!(level, Level::RawStep(_) | Level::FractionStep(_)))
+        {
+            return Err(ScreenpadError::Usage(format!(
+                "Schedule levels cannot be steps like `{}`",
+                level
+            )));
+        }
+
+        for point in &mut points {
+            point.0 = point.0.rem_euclid(MINUTES_PER_DAY);
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { points })
+    }
+
+    /// Brightness at `minute` on a device with range [0->max], interpolated
+    /// on the perceived scale of `curve` and wrapping around midnight
+    pub fn brightness_at(&self, minute: f64, max: i16, curve: &Curve) -> Result<i16> {
+        let minute = minute.rem_euclid(MINUTES_PER_DAY);
+        let perceived = |level: Level| -> Result<f64> {
+            Ok(curve.perceived(level.resolve(0, max, curve)?, max))
+        };
+
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        let (before, after) = match self.points.iter().position(|point| point.0 > minute) {
+            Some(0) => ((last.0 - MINUTES_PER_DAY, last.1), first),
+            None => (last, (first.0 + MINUTES_PER_DAY, first.1)),
+            Some(index) => (self.points[index - 1], self.points[index]),
+        };
+
+        let from = perceived(before.1)?;
+        let to = perceived(after.1)?;
+        let span = after.0 - before.0;
+        let progress = if span > 0.0 {
+            (minute - before.0) / span
+        } else {
+            1.0
+        };
+        Ok(curve.raw_brightness(from + (to - from) * progress, max))
+    }
+}
+
+/// Set the scheduled brightness unless the screenpad is off or dimmed.
+/// Stays above the dim threshold, so the screenpad keeps reading as on
+pub fn apply(screenpad: &Screenpad, brightness: i16) -> Result<Option<i16>> {
+    screenpad.adjust(brightness)
+}
+
+/// Follow the schedule in the background of the daemon. The brightness is
+/// only set when the scheduled value moves, so changes by hand stick until
+/// then
+pub fn spawn(tasks: &Tasks, cfg: &ScheduleConfig) {
+    let tasks = tasks.clone();
+    let cfg = cfg.clone();
+    let poll = Duration::from_millis(cfg.poll_ms.max(1));
+    let last_target = Arc::new(Mutex::new(None));
+
+    thread::spawn(move || loop {
+        let now = LocalTime::now();
+        match Schedule::for_day(&cfg, &now) {
+            Ok(schedule) => {
+                let last_target = last_target.clone();
+                let submitted = tasks.submit(move |screenpad, _| {
+                    let result = screenpad.max_brightness().and_then(|max| {
+                        schedule.brightness_at(now.minute, max, &screenpad.curve())
+                    });
+                    let mut last_target = last_target.lock().expect("schedule lock poisoned");
+                    match result {
+                        Ok(target) if *last_target != Some(target) => {
+                            *last_target = Some(target);
+                            if let Err(err) = apply(screenpad, target) {
+                                eprintln!("schedule: {}", err);
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(err) => eprintln!("schedule: {}", err),
+                    }
+                });
+                if !submitted {
+                    return;
+                }
+            }
+            Err(err) => eprintln!("schedule: {}", err),
+        }
+        thread::sleep(poll);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> TimeOfDay {
+        text.parse().unwrap()
+    }
+
+    fn date(day_of_year: u16, utc_offset_hours: f64) -> LocalTime {
+        LocalTime {
+            day_of_year,
+            minute: 0.0,
+            utc_offset: utc_offset_hours * 60.0,
+        }
+    }
+
+    /// Sunrise and sunset within 5 minutes of `sunrise` and `sunset`
+    fn assert_sun(daylight: Daylight, sunrise: &str, sunset: &str) {
+        let Daylight::Sun {
+            sunrise: rise,
+            sunset: set,
+        } = daylight
+        else {
+            panic!("expected a sunrise and sunset, got {:?}", daylight);
+        };
+        for (computed, expected) in [(rise, sunrise), (set, sunset)] {
+            let expected = time(expected).minutes() as f64;
+            assert!(
+                (computed - expected).abs() <= 5.0,
+                "{} is not close to {}",
+                TimeOfDay::from_minutes(computed),
+                TimeOfDay::from_minutes(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(time("00:00").minutes(), 0);
+        assert_eq!(time("07:30").minutes(), 450);
+        assert_eq!(time("23:59").minutes(), 1439);
+        assert_eq!(time("7:05").to_string(), "07:05");
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        for text in [
+            "", "12", "24:00", "12:60", "-1:00", "ab:cd", "12:30:00", " 12:30",
+        ] {
+            let err = text.parse::<TimeOfDay>().unwrap_err();
+            assert_eq!(err.exit_code(), 2, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn minutes_wrap_into_one_day() {
+        assert_eq!(TimeOfDay::from_minutes(-30.0).to_string(), "23:30");
+        assert_eq!(TimeOfDay::from_minutes(1470.0).to_string(), "00:30");
+        assert_eq!(TimeOfDay::from_minutes(1439.9).to_string(), "23:59");
+    }
+
+    #[test]
+    fn computes_known_sunrise_and_sunset() {
+        // Berlin on June 21st and December 21st
+        assert_sun(
+            Daylight::compute(52.52, 13.40, &date(172, 2.0)),
+            "04:43",
+            "21:33",
+        );
+        assert_sun(
+            Daylight::compute(52.52, 13.40, &date(355, 1.0)),
+            "08:15",
+            "15:54",
+        );
+        // New York on June 21st
+        assert_sun(
+            Daylight::compute(40.71, -74.01, &date(172, -4.0)),
+            "05:25",
+            "20:31",
+        );
+        // Sydney on June 21st, winter in the south
+        assert_sun(
+            Daylight::compute(-33.87, 151.21, &date(172, 10.0)),
+            "07:00",
+            "16:54",
+        );
+    }
+
+    #[test]
+    fn computes_polar_day_and_night() {
+        // Tromsø
+        assert_eq!(
+            Daylight::compute(69.65, 18.96, &date(172, 2.0)),
+            Daylight::PolarDay
+        );
+        assert_eq!(
+            Daylight::compute(69.65, 18.96, &date(355, 1.0)),
+            Daylight::PolarNight
+        );
+        // McMurdo Station
+        assert_eq!(
+            Daylight::compute(-77.85, 166.67, &date(355, 13.0)),
+            Daylight::PolarDay
+        );
+        assert_eq!(
+            Daylight::compute(-77.85, 166.67, &date(172, 12.0)),
+            Daylight::PolarNight
+        );
+    }
+
+    #[test]
+    fn sun_schedule_needs_a_position() {
+        let cfg = ScheduleConfig {
+            mode: ScheduleMode::Sun,
+            ..ScheduleConfig::default()
+        };
+        let err = Schedule::for_day(&cfg, &date(172, 2.0)).unwrap_err();
+        assert_eq!(err.exit_code(), 7);
+
+        let cfg = ScheduleConfig {
+            latitude: Some(95.0),
+            longitude: Some(13.40),
+            ..cfg
+        };
+        assert_eq!(cfg.validate().unwrap_err().exit_code(), 7);
+    }
+
+    #[test]
+    fn polar_day_keeps_the_day_level() {
+        let cfg = ScheduleConfig {
+            mode: ScheduleMode::Sun,
+            latitude: Some(69.65),
+            longitude: Some(18.96),
+            ..ScheduleConfig::default()
+        };
+        let schedule = Schedule::for_day(&cfg, &date(172, 2.0)).unwrap();
+        for minute in [0.0, 360.0, 720.0, 1380.0] {
+            assert_eq!(
+                schedule.brightness_at(minute, 100, &Curve::Linear).unwrap(),
+                80
+            );
+        }
+    }
+
+    #[test]
+    fn interpolates_around_midnight() {
+        let cfg = ScheduleConfig {
+            points: BTreeMap::from([
+                (time("02:00"), Level::Fraction(1.0)),
+                (time("22:00"), Level::Fraction(0.0)),
+            ]),
+            ..ScheduleConfig::default()
+        };
+        let schedule = Schedule::for_day(&cfg, &date(1, 0.0)).unwrap();
+        let at = |text: &str| {
+            let minute = time(text).minutes() as f64;
+            schedule.brightness_at(minute, 100, &Curve::Linear).unwrap()
+        };
+
+        assert_eq!(at("22:00"), 0);
+        assert_eq!(at("23:00"), 25);
+        assert_eq!(at("00:00"), 50);
+        assert_eq!(at("01:00"), 75);
+        assert_eq!(at("02:00"), 100);
+        assert_eq!(at("12:00"), 50);
+        assert_eq!(
+            schedule
+                .brightness_at(1440.0 + 60.0, 100, &Curve::Linear)
+                .unwrap(),
+            75
+        );
+    }
+
+    #[test]
+    fn rejects_empty_and_step_schedules() {
+        let cfg = ScheduleConfig {
+            points: BTreeMap::new(),
+            ..ScheduleConfig::default()
+        };
+        assert!(Schedule::for_day(&cfg, &date(1, 0.0)).is_err());
+
+        let cfg = ScheduleConfig {
+            points: BTreeMap::from([(time("12:00"), Level::FractionStep(0.1))]),
+            ..ScheduleConfig::default()
+        };
+        assert!(Schedule::for_day(&cfg, &date(1, 0.0)).is_err());
+    }
+}
@@ -0,0 +1,874 @@
+use crate::backend::ScreenpadBackend;
+use crate::config::{default_cycle, Config, LedConfig};
+use crate::curve::Curve;
+use crate::discovery;
+use crate::error::{Result, ScreenpadError};
+use crate::fade::{self, Clock, Fade, SystemClock};
+use crate::level::Level;
+use crate::state;
+use crate::touch::TouchInput;
+use crate::watch::ChangeWatcher;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScreenState {
+    On,
+    Off,
+    Dim,
+}
+
+impl fmt::Display for ScreenState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ScreenState::On => "on",
+            ScreenState::Off => "off",
+            ScreenState::Dim => "dim",
+        })
+    }
+}
+
+/// State before and after a power operation. Both are equal when nothing
+/// had to change
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub from: ScreenState,
+    /// State reached, short of the one asked for when interrupted
+    pub to: ScreenState,
+    /// A later command cut the fade short
+    pub interrupted: bool,
+}
+
+impl Transition {
+    pub fn changed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+/// Names of the cycle entries before and after a `cycle`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleStep {
+    pub from: String,
+    pub to: String,
+    /// A later command cut the fade to `to` short
+    pub interrupted: bool,
+}
+
+/// What a preset name stands for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Level(Level),
+    State(ScreenState),
+}
+
+/// Brightness before and after a step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BrightnessChange {
+    pub from: i16,
+    pub to: i16,
+    /// A later command cut the fade short, `to` is where it stopped
+    pub interrupted: bool,
+}
+
+impl BrightnessChange {
+    pub fn changed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+/// Handle on a screenpad device
+pub struct Screenpad {
+    dev: Box<dyn ScreenpadBackend>,
+    backup_file: PathBuf,
+    legacy_backup_file: Option<PathBuf>,
+    curve: Curve,
+    fade: Fade,
+    clock: Box<dyn Clock>,
+    interrupted: Box<dyn Fn() -> bool + Send + Sync>,
+    touch: Option<TouchInput>,
+    presets: BTreeMap<String, Level>,
+    cycle: Vec<String>,
+    dim_level: Level,
+    dim_threshold: Level,
+}
+
+impl Screenpad {
+    pub fn new(dev: Box<dyn ScreenpadBackend>) -> Self {
+        Self {
+            dev,
+            backup_file: state::backup_file(),
+            legacy_backup_file: Some(state::legacy_backup_file()),
+            curve: Curve::Linear,
+            fade: Fade::default(),
+            clock: Box::<SystemClock>::default(),
+            interrupted: Box::new(|| false),
+            touch: None,
+            presets: BTreeMap::new(),
+            cycle: default_cycle(),
+            dim_level: Level::Raw(1),
+            dim_threshold: Level::Raw(1),
+        }
+    }
+
+    /// Open the auto-detected screenpad under /sys
+    pub fn discover() -> Result<Self> {
+        Self::open(Path::new(discovery::DEFAULT_SYSFS_ROOT), None)
+    }
+
+    /// Open `device`, or the auto-detected screenpad, under `sysfs_root`
+    pub fn open(sysfs_root: &Path, device: Option<&str>) -> Result<Self> {
+        Ok(Self::new(discovery::open(sysfs_root, device)?)
+            .with_touch(TouchInput::new(sysfs_root, &[])))
+    }
+
+    /// Open the LED class device `name` under `sysfs_root`, e.g.
+    /// `asus::kbd_backlight`, with the backup file from `cfg`
+    pub fn open_led(sysfs_root: &Path, name: &str, cfg: &LedConfig) -> Result<Self> {
+        let backup_file = cfg
+            .backup_file
+            .clone()
+            .unwrap_or_else(|| state::led_backup_file(name));
+
+        Ok(Self::new(discovery::find_led(sysfs_root, name)?.open()).with_backup_file(backup_file))
+    }
+
+    /// Keep the brightness backup in `path` instead of the XDG state directory
+    pub fn with_backup_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.backup_file = path.into();
+        self.legacy_backup_file = None;
+        self
+    }
+
+    /// Take steps and percentages on `curve` instead of linearly
+    pub fn with_curve(mut self, curve: Curve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn curve(&self) -> Curve {
+        self.curve
+    }
+
+    /// Fade to the new brightness on every state change
+    pub fn with_fade(mut self, fade: Fade) -> Self {
+        self.fade = fade;
+        self
+    }
+
+    /// Inhibit `touch` while the screen is off
+    pub fn with_touch(mut self, touch: TouchInput) -> Self {
+        self.touch = Some(touch);
+        self
+    }
+
+    /// Named brightness levels for `preset` and `cycle`
+    pub fn with_presets(mut self, presets: BTreeMap<String, Level>) -> Self {
+        self.presets = presets;
+        self
+    }
+
+    /// Go through these preset or state names on `cycle`
+    pub fn with_cycle(mut self, cycle: Vec<String>) -> Self {
+        self.cycle = cycle;
+        self
+    }
+
+    /// Dim to `level`, and read brightnesses up to `threshold` as dimmed
+    pub fn with_dim(mut self, level: Level, threshold: Level) -> Self {
+        self.dim_level = level;
+        self.dim_threshold = threshold;
+        self
+    }
+
+    /// Take curve, fade, presets, cycle, dim level and touch patterns from
+    /// `cfg`
+    pub fn apply_config(&mut self, cfg: &Config) {
+        self.dim_level = cfg.dim_level;
+        self.dim_threshold = cfg.dim_threshold;
+        self.curve = cfg.curve;
+        self.fade = cfg.fade;
+        self.presets = cfg.presets.clone();
+        self.cycle = cfg.cycle.clone();
+        if let Some(touch) = &mut self.touch {
+            touch.set_patterns(&cfg.touch.patterns);
+        }
+    }
+
+    /// Time fades with `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Abandon a running fade as soon as `interrupted` returns true
+    pub fn with_interrupt(
+        mut self,
+        interrupted: impl Fn() -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.interrupted = Box::new(interrupted);
+        self
+    }
+
+    pub fn backup_file(&self) -> &Path {
+        &self.backup_file
+    }
+
+    /// Pick up a backup written by an older version. Only `on` does, reads
+    /// leave the state directory alone
+    fn migrate_backup(&self) -> Result<()> {
+        match &self.legacy_backup_file {
+            Some(legacy) => state::migrate_legacy_backup(legacy, &self.backup_file),
+            None => Ok(()),
+        }
+    }
+
+    pub fn backend(&self) -> &dyn ScreenpadBackend {
+        self.dev.as_ref()
+    }
+
+    pub fn get_brightness(&self) -> Result<i16> {
+        self.dev.read()
+    }
+
+    pub fn max_brightness(&self) -> Result<i16> {
+        self.dev.max()
+    }
+
+    /// Overwite brightness, failing with `OutOfRange` outside [0->max]
+    pub fn overwrite_brightness(&self, value: i16) -> Result<()> {
+        let max_brightness = self.max_brightness()?;
+
+        if !(0..=max_brightness).contains(&value) {
+            return Err(ScreenpadError::OutOfRange {
+                value: value.into(),
+                max: max_brightness,
+            });
+        }
+
+        self.write_brightness(value, false).map(|_| ())
+    }
+
+    /// Set brightness from a raw value, percentage, fraction or step.
+    /// Returns the new brightness
+    pub fn set_level(&self, level: Level) -> Result<i16> {
+        let value = level.resolve(self.get_brightness()?, self.max_brightness()?, &self.curve)?;
+        self.write_brightness(value, false)?;
+        Ok(value)
+    }
+
+    /// increment brightness by +/-ve value, stopping at 0 and max. On a
+    /// non-linear curve the value is a step of value/max on the perceived scale
+    pub fn increment_brightness(&self, value: i16) -> Result<BrightnessChange> {
+        let current_brightness = self.get_brightness()?;
+        let max_brightness = self.max_brightness()?;
+
+        let step = match self.curve {
+            Curve::Linear => Level::RawStep(value.into()),
+            _ => Level::FractionStep(value as f64 / max_brightness.max(1) as f64),
+        };
+        let new_brightness = step.resolve(current_brightness, max_brightness, &self.curve)?;
+
+        if new_brightness != current_brightness {
+            self.write_brightness(new_brightness, false)?;
+        }
+
+        Ok(BrightnessChange {
+            from: current_brightness,
+            to: new_brightness,
+            interrupted: false,
+        })
+    }
+
+    /// Store current brightness in file
+    pub fn backup_brightness(&self) -> Result<()> {
+        let current_brightness = self.get_brightness()?;
+        state::write_atomic(&self.backup_file, &current_brightness.to_string())
+    }
+
+    /// Previous brightness value stored by `backup_brightness`
+    pub fn restore_brightness(&self) -> Result<i16> {
+        self.migrate_backup()?;
+        self.stored_brightness()?
+            .ok_or_else(|| ScreenpadError::MissingBackup(self.backup_file.clone()))
+    }
+
+    /// Brightness in the backup, `None` when there is none
+    pub fn stored_brightness(&self) -> Result<Option<i16>> {
+        let mut prev_brightness = match fs::read_to_string(&self.backup_file) {
+            Ok(prev_brightness) => prev_brightness,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(ScreenpadError::io(&self.backup_file, err)),
+        };
+
+        if prev_brightness.ends_with('\n') {
+            prev_brightness.pop();
+        }
+
+        prev_brightness
+            .parse::<i16>()
+            .map(Some)
+            .map_err(|_| ScreenpadError::Parse {
+                path: self.backup_file.clone(),
+                value: prev_brightness,
+            })
+    }
+
+    /// Watcher for changes of the brightness. Callers still compare the
+    /// brightness after each wait, not every change can be signalled
+    pub fn watcher(&self) -> ChangeWatcher {
+        ChangeWatcher::new(
+            self.dev.watch_dir().as_deref(),
+            self.dev.watch_path().as_deref(),
+        )
+    }
+
+    /// Raw brightness of the dim setting `name`. A level the device cannot
+    /// take is a mistake in the config, not in the command
+    fn resolve_dim(&self, name: &str, level: Level) -> Result<i16> {
+        let max = self.max_brightness()?;
+        let invalid = || {
+            ScreenpadError::InvalidConfig(format!(
+                "`{}` of `{}` is outside the brightness range [0->{}]",
+                name, level, max
+            ))
+        };
+
+        if matches!(level, Level::RawStep(_) | Level::FractionStep(_)) {
+            return Err(invalid());
+        }
+        level.resolve(0, max, &self.curve).map_err(|_| invalid())
+    }
+
+    /// Raw brightness of `dim`, at least 1 so dimming never turns off. A
+    /// `dim` preset takes the place of the dim level
+    pub fn dim_brightness(&self) -> Result<i16> {
+        let brightness = match self.presets.get("dim") {
+            Some(level) => self.resolve_dim("presets.dim", *level)?,
+            None => self.resolve_dim("dim_level", self.dim_level)?,
+        };
+        Ok(brightness.max(1))
+    }
+
+    /// Highest raw brightness that reads as dimmed. Never below the dim
+    /// brightness, so a dimmed screen always reads as dimmed
+    pub fn dim_threshold(&self) -> Result<i16> {
+        let threshold = self.resolve_dim("dim_threshold", self.dim_threshold)?;
+        Ok(threshold.max(self.dim_brightness()?))
+    }
+
+    /// State a brightness reads as
+    /// 0 -> off
+    /// 1..=dim threshold -> dim
+    /// above -> on
+    pub fn state_of(&self, brightness: i16) -> Result<ScreenState> {
+        Ok(match brightness {
+            0 => ScreenState::Off,
+            brightness if brightness <= self.dim_threshold()? => ScreenState::Dim,
+            _ => ScreenState::On,
+        })
+    }
+
+    /// Get current state of display
+    pub fn screen_state(&self) -> Result<ScreenState> {
+        self.state_of(self.get_brightness()?)
+    }
+
+    /// Move to `value` for automatic adjustments, only while on and never so
+    /// low the screenpad reads as dimmed or off. Returns the new brightness,
+    /// `None` when nothing was written
+    pub fn adjust(&self, value: i16) -> Result<Option<i16>> {
+        // a screen dimmed or turned off, by hand or when idle, stays that way
+        if self.screen_state()? != ScreenState::On {
+            return Ok(None);
+        }
+        let lowest = self.dim_threshold()? + 1;
+        let value = value.max(lowest).min(self.max_brightness()?);
+        if value == self.get_brightness()? {
+            return Ok(None);
+        }
+        self.write_brightness(value, false)?;
+        Ok(Some(value))
+    }
+
+    /// Move to `value`, fading if configured. Returns false when the fade
+    /// was interrupted
+    pub fn fade_to(&self, value: i16) -> Result<bool> {
+        self.write_brightness(value, true)
+    }
+
+    /// The one way brightness is written. The touchscreen is resumed when
+    /// the brightness leaves 0 and inhibited once it reaches 0. Returns false
+    /// when the fade was interrupted
+    fn write_brightness(&self, value: i16, fade: bool) -> Result<bool> {
+        let from = self.get_brightness()?;
+
+        let reached = if fade {
+            fade::run(
+                self.dev.as_ref(),
+                from,
+                value,
+                &self.fade,
+                &self.curve,
+                self.clock.as_ref(),
+                &self.interrupted,
+            )?
+        } else {
+            self.dev.write(value)?;
+            true
+        };
+
+        // taps on a dark screen would reach the desktop
+        let to = if reached {
+            value
+        } else {
+            self.get_brightness()?
+        };
+        if to == 0 {
+            self.inhibit_touch(true);
+        } else if from == 0 {
+            self.inhibit_touch(false);
+        }
+        Ok(reached)
+    }
+
+    /// Inhibit or resume the touchscreen. Failing to is only a warning,
+    /// `inhibited` is often root-only while the brightness is not
+    fn inhibit_touch(&self, inhibited: bool) {
+        if let Some(Err(err)) = self
+            .touch
+            .as_ref()
+            .map(|touch| touch.set_inhibited(inhibited))
+        {
+            eprintln!("touch: {}", err);
+        }
+    }
+
+    /// Move to `value` from a screen reading as `from`. Leaving on for a
+    /// brightness that reads as dim or off backs up the brightness for `on`.
+    /// Returns false when the fade was interrupted
+    fn move_to(&self, from: ScreenState, value: i16) -> Result<bool> {
+        if from == ScreenState::On && self.state_of(value)? != ScreenState::On {
+            self.backup_brightness()?;
+        }
+        self.fade_to(value)
+    }
+
+    fn transition(&self, from: ScreenState, to: ScreenState) -> Result<Transition> {
+        let target = match (from, to) {
+            (from, to) if from == to => {
+                return Ok(Transition {
+                    from,
+                    to,
+                    interrupted: false,
+                })
+            }
+            (_, ScreenState::On) => self.restore_brightness()?,
+            (_, ScreenState::Dim) => self.dim_brightness()?,
+            (_, ScreenState::Off) => 0,
+        };
+
+        if !self.move_to(from, target)? {
+            return Ok(Transition {
+                from,
+                to: self.screen_state()?,
+                interrupted: true,
+            });
+        }
+        Ok(Transition {
+            from,
+            to,
+            interrupted: false,
+        })
+    }
+
+    /// Turn on at the brightness stored by the last `off`
+    pub fn on(&self) -> Result<Transition> {
+        self.transition(self.screen_state()?, ScreenState::On)
+    }
+
+    pub fn off(&self) -> Result<Transition> {
+        self.transition(self.screen_state()?, ScreenState::Off)
+    }
+
+    pub fn dim(&self) -> Result<Transition> {
+        self.transition(self.screen_state()?, ScreenState::Dim)
+    }
+
+    /// Toggle between on and off. A dimmed screen is left alone
+    pub fn toggle(&self) -> Result<Transition> {
+        match self.screen_state()? {
+            ScreenState::On => self.transition(ScreenState::On, ScreenState::Off),
+            ScreenState::Off => self.transition(ScreenState::Off, ScreenState::On),
+            ScreenState::Dim => Ok(Transition {
+                from: ScreenState::Dim,
+                to: ScreenState::Dim,
+                interrupted: false,
+            }),
+        }
+    }
+
+    /// Preset `name`, or the state of that name when no preset has it. A
+    /// `dim` preset sets the level of the dim state, so `dim` stays a state
+    fn target(&self, name: &str) -> Result<Target> {
+        if name == "dim" {
+            return Ok(Target::State(ScreenState::Dim));
+        }
+        if let Some(level) = self.presets.get(name) {
+            if matches!(level, Level::RawStep(_) | Level::FractionStep(_)) {
+                return Err(ScreenpadError::Usage(format!(
+                    "Preset `{}` cannot be a step like `{}`",
+                    name, level
+                )));
+            }
+            return Ok(Target::Level(*level));
+        }
+
+        match name {
+            "on" => Ok(Target::State(ScreenState::On)),
+            "dim" => Ok(Target::State(ScreenState::Dim)),
+            "off" => Ok(Target::State(ScreenState::Off)),
+            _ => Err(ScreenpadError::Usage(format!("Unknown preset `{}`", name))),
+        }
+    }
+
+    /// Go to the preset or state `name`. Leaving on for a brightness that
+    /// reads as dim or off backs up the brightness, as `dim` and `off` do
+    pub fn preset(&self, name: &str) -> Result<BrightnessChange> {
+        let from = self.get_brightness()?;
+
+        let interrupted = match self.target(name)? {
+            Target::State(to) => self.transition(self.state_of(from)?, to)?.interrupted,
+            Target::Level(level) => {
+                let to = level.resolve(from, self.max_brightness()?, &self.curve)?;
+                !self.move_to(self.state_of(from)?, to)?
+            }
+        };
+
+        Ok(BrightnessChange {
+            from,
+            to: self.get_brightness()?,
+            interrupted,
+        })
+    }
+
+    /// Move to the entry after the current one in the cycle, [on -> dim ->
+    /// off] unless configured (loops). A brightness matching no entry moves
+    /// to the first
+    pub fn cycle(&self) -> Result<CycleStep> {
+        if self.cycle.is_empty() {
+            return Err(ScreenpadError::Usage("The cycle is empty".to_string()));
+        }
+        let brightness = self.get_brightness()?;
+        let max = self.max_brightness()?;
+        let state = self.state_of(brightness)?;
+
+        let targets = self
+            .cycle
+            .iter()
+            .map(|name| self.target(name))
+            .collect::<Result<Vec<_>>>()?;
+        // an exact preset says more than a state
+        let current = targets
+            .iter()
+            .position(|target| match target {
+                Target::Level(level) => {
+                    level.resolve(brightness, max, &self.curve).ok() == Some(brightness)
+                }
+                Target::State(_) => false,
+            })
+            .or_else(|| {
+                targets
+                    .iter()
+                    .position(|target| *target == Target::State(state))
+            });
+
+        let next = current.map_or(0, |index| (index + 1) % self.cycle.len());
+        let from = match current {
+            Some(index) => self.cycle[index].clone(),
+            None => state.to_string(),
+        };
+
+        let change = self.preset(&self.cycle[next])?;
+        Ok(CycleStep {
+            from,
+            to: self.cycle[next].clone(),
+            interrupted: change.interrupted,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempDir};
+
+    const INHIBITED: &str = "class/input/input11/inhibited";
+
+    /// Screenpad at `brightness` of 255 with a fake touchscreen
+    fn with_touch(dir: &TempDir, brightness: i16) -> Screenpad {
+        dir.write("class/input/input11/name", "ELAN9009:00 04F3:2C1B\n");
+        dir.write(INHIBITED, "0\n");
+        let touch = TouchInput::new(dir.path(), &["ELAN9009:00*".to_string()]);
+        testutil::screenpad(dir, brightness, 255).with_touch(touch)
+    }
+
+    #[test]
+    fn off_and_on_inhibit_touch() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180);
+
+        screenpad.off().unwrap();
+        assert_eq!(dir.read(INHIBITED), "1");
+        screenpad.on().unwrap();
+        assert_eq!(dir.read(INHIBITED), "0");
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+    }
+
+    #[test]
+    fn lighting_up_by_hand_resumes_touch() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180);
+
+        screenpad.off().unwrap();
+        screenpad.increment_brightness(15).unwrap();
+        assert_eq!(dir.read(INHIBITED), "0");
+
+        screenpad.off().unwrap();
+        screenpad.set_level(Level::Fraction(0.5)).unwrap();
+        assert_eq!(dir.read(INHIBITED), "0");
+
+        screenpad.off().unwrap();
+        screenpad.overwrite_brightness(60).unwrap();
+        assert_eq!(dir.read(INHIBITED), "0");
+    }
+
+    #[test]
+    fn darkening_by_hand_inhibits_touch() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 10);
+
+        screenpad.increment_brightness(-15).unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+        assert_eq!(dir.read(INHIBITED), "1");
+
+        screenpad.set_level(Level::Raw(5)).unwrap();
+        screenpad.set_level(Level::Raw(0)).unwrap();
+        assert_eq!(dir.read(INHIBITED), "1");
+    }
+
+    #[test]
+    fn touch_that_cannot_be_inhibited_does_not_block_the_screen() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180);
+        // writing a directory fails like a root-only `inhibited`
+        fs::remove_file(dir.path().join(INHIBITED)).unwrap();
+        fs::create_dir(dir.path().join(INHIBITED)).unwrap();
+
+        screenpad.off().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+        screenpad.on().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+        screenpad.off().unwrap();
+        screenpad.set_level(Level::Raw(60)).unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 60);
+    }
+
+    #[test]
+    fn dimming_leaves_touch_alone() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180);
+
+        screenpad.dim().unwrap();
+        assert_eq!(dir.read(INHIBITED), "0");
+        screenpad.increment_brightness(-15).unwrap();
+        assert_eq!(dir.read(INHIBITED), "1");
+    }
+
+    #[test]
+    fn cycling_out_of_off_to_a_level_resumes_touch() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180)
+            .with_presets(BTreeMap::from([(
+                "reading".to_string(),
+                Level::Fraction(0.4),
+            )]))
+            .with_cycle(vec!["reading".to_string(), "off".to_string()]);
+
+        assert_eq!(screenpad.cycle().unwrap().to, "reading");
+        assert_eq!(screenpad.get_brightness().unwrap(), 102);
+
+        assert_eq!(screenpad.cycle().unwrap().to, "off");
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+        assert_eq!(dir.read(INHIBITED), "1");
+
+        let step = screenpad.cycle().unwrap();
+        assert_eq!((step.from.as_str(), step.to.as_str()), ("off", "reading"));
+        assert_eq!(screenpad.get_brightness().unwrap(), 102);
+        assert_eq!(dir.read(INHIBITED), "0");
+    }
+
+    #[test]
+    fn level_preset_at_zero_acts_like_off() {
+        let dir = TempDir::new();
+        let screenpad = with_touch(&dir, 180)
+            .with_presets(BTreeMap::from([("blank".to_string(), Level::Raw(0))]));
+
+        let change = screenpad.preset("blank").unwrap();
+        assert_eq!((change.from, change.to), (180, 0));
+        assert_eq!(dir.read(INHIBITED), "1");
+        assert_eq!(dir.read("brightness_backup"), "180");
+
+        screenpad.on().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+        assert_eq!(dir.read(INHIBITED), "0");
+    }
+
+    #[test]
+    fn level_preset_that_dims_backs_up() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255)
+            .with_presets(BTreeMap::from([("low".to_string(), Level::Raw(1))]));
+
+        screenpad.preset("low").unwrap();
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::Dim);
+        assert_eq!(dir.read("brightness_backup"), "180");
+
+        // from dim the backup stays the brightness from before
+        screenpad.preset("low").unwrap();
+        screenpad.off().unwrap();
+        screenpad.on().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+    }
+
+    #[test]
+    fn states_follow_the_dim_threshold() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255).with_dim(Level::Raw(5), Level::Raw(20));
+
+        assert_eq!(screenpad.dim_brightness().unwrap(), 5);
+        assert_eq!(screenpad.dim_threshold().unwrap(), 20);
+        assert_eq!(screenpad.state_of(0).unwrap(), ScreenState::Off);
+        assert_eq!(screenpad.state_of(1).unwrap(), ScreenState::Dim);
+        assert_eq!(screenpad.state_of(20).unwrap(), ScreenState::Dim);
+        assert_eq!(screenpad.state_of(21).unwrap(), ScreenState::On);
+
+        screenpad.dim().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 5);
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::Dim);
+    }
+
+    #[test]
+    fn dim_threshold_never_sits_below_the_dim_level() {
+        let dir = TempDir::new();
+        let screenpad =
+            testutil::screenpad(&dir, 180, 255).with_dim(Level::Fraction(0.2), Level::Raw(10));
+
+        assert_eq!(screenpad.dim_brightness().unwrap(), 51);
+        assert_eq!(screenpad.dim_threshold().unwrap(), 51);
+        screenpad.dim().unwrap();
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::Dim);
+
+        // a dim level of 0 still dims instead of turning off
+        let screenpad = testutil::screenpad(&dir, 180, 255).with_dim(Level::Raw(0), Level::Raw(0));
+        assert_eq!(screenpad.dim_brightness().unwrap(), 1);
+        assert_eq!(screenpad.state_of(1).unwrap(), ScreenState::Dim);
+    }
+
+    #[test]
+    fn dim_levels_out_of_range_are_config_errors() {
+        let dir = TempDir::new();
+        for (level, threshold) in [
+            (Level::Raw(300), Level::Raw(1)),
+            (Level::Raw(1), Level::Fraction(1.5)),
+            (Level::RawStep(5), Level::Raw(1)),
+        ] {
+            let screenpad = testutil::screenpad(&dir, 180, 255).with_dim(level, threshold);
+            assert_eq!(screenpad.screen_state().unwrap_err().exit_code(), 7);
+        }
+    }
+
+    #[test]
+    fn adjust_stays_above_the_dim_threshold() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255).with_dim(Level::Raw(5), Level::Raw(20));
+
+        assert_eq!(screenpad.adjust(3).unwrap(), Some(21));
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::On);
+        assert_eq!(screenpad.adjust(21).unwrap(), None);
+        assert_eq!(screenpad.adjust(500).unwrap(), Some(255));
+        assert_eq!(screenpad.adjust(100).unwrap(), Some(100));
+    }
+
+    #[test]
+    fn adjust_leaves_a_dimmed_or_dark_screen() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255).with_dim(Level::Raw(5), Level::Raw(20));
+
+        screenpad.overwrite_brightness(15).unwrap();
+        assert_eq!(screenpad.adjust(100).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 15);
+
+        screenpad.off().unwrap();
+        assert_eq!(screenpad.adjust(100).unwrap(), None);
+        assert_eq!(screenpad.get_brightness().unwrap(), 0);
+    }
+
+    #[test]
+    fn a_dim_preset_is_the_one_dim_level() {
+        let dir = TempDir::new();
+        let screenpad = testutil::screenpad(&dir, 180, 255)
+            .with_presets(BTreeMap::from([("dim".to_string(), Level::Raw(40))]));
+
+        screenpad.dim().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 40);
+        assert_eq!(screenpad.screen_state().unwrap(), ScreenState::Dim);
+        screenpad.on().unwrap();
+
+        screenpad.preset("dim").unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 40);
+        assert_eq!(dir.read("brightness_backup"), "180");
+        screenpad.on().unwrap();
+
+        assert_eq!(screenpad.cycle().unwrap().to, "dim");
+        assert_eq!(screenpad.get_brightness().unwrap(), 40);
+        assert_eq!(screenpad.cycle().unwrap().to, "off");
+        assert_eq!(screenpad.cycle().unwrap().to, "on");
+        assert_eq!(screenpad.get_brightness().unwrap(), 180);
+    }
+
+    /// Screenpad that is off, with a legacy backup of `contents` in `dir`
+    fn with_legacy(dir: &TempDir, contents: &str) -> Screenpad {
+        Screenpad {
+            legacy_backup_file: Some(dir.write("legacy_backup", contents)),
+            ..testutil::screenpad(dir, 0, 255)
+        }
+    }
+
+    #[test]
+    fn only_on_migrates_the_legacy_backup() {
+        let dir = TempDir::new();
+        let screenpad = with_legacy(&dir, "150\n");
+
+        assert_eq!(screenpad.stored_brightness().unwrap(), None);
+        screenpad.set_level(Level::Raw(0)).unwrap();
+        assert!(!dir.path().join("brightness_backup").exists());
+
+        screenpad.on().unwrap();
+        assert_eq!(screenpad.get_brightness().unwrap(), 150);
+        assert_eq!(dir.read("brightness_backup"), "150");
+        assert_eq!(dir.read("legacy_backup"), "150");
+    }
+
+    #[test]
+    fn a_foreign_legacy_file_is_not_a_backup() {
+        let dir = TempDir::new();
+        let screenpad = with_legacy(&dir, "0.75\n");
+
+        assert_eq!(screenpad.on().unwrap_err().exit_code(), 6);
+        assert_eq!(dir.read("legacy_backup"), "0.75");
+        assert!(!dir.path().join("brightness_backup").exists());
+    }
+}
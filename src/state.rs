@@ -0,0 +1,211 @@
+use crate::config::APP_NAME;
+use crate::error::{Result, ScreenpadError};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const BACKUP_FILE_NAME: &str = "brightness_backup";
+
+/// Absolute path from an environment variable, relative ones are ignored as
+/// the XDG base directory spec asks
+fn env_dir(name: &str) -> Option<PathBuf> {
+    absolute(env::var_os(name))
+}
+
+fn absolute(value: Option<OsString>) -> Option<PathBuf> {
+    value.map(PathBuf::from).filter(|path| path.is_absolute())
+}
+
+/// `$XDG_STATE_HOME/screenpadctl`, defaulting to `~/.local/state/screenpadctl`
+pub fn state_dir() -> PathBuf {
+    state_dir_in(env_dir("XDG_STATE_HOME"), env_dir("HOME"))
+}
+
+/// State directory for `$XDG_STATE_HOME` and `$HOME`
+fn state_dir_in(state_home: Option<PathBuf>, home: Option<PathBuf>) -> PathBuf {
+    let base = state_home
+        .or_else(|| home.map(|home| home.join(".local/state")))
+        .unwrap_or_else(env::temp_dir);
+
+    base.join(APP_NAME)
+}
+
+/// `$XDG_RUNTIME_DIR/screenpadctl` for files that must not outlive the
+/// session, falling back to the state directory
+pub fn runtime_dir() -> PathBuf {
+    runtime_dir_in(env_dir("XDG_RUNTIME_DIR"), state_dir())
+}
+
+fn runtime_dir_in(runtime_dir: Option<PathBuf>, state_dir: PathBuf) -> PathBuf {
+    match runtime_dir {
+        Some(base) => base.join(APP_NAME),
+        None => state_dir,
+    }
+}
+
+/// Where the brightness is stored while the screen is off or dimmed
+pub fn backup_file() -> PathBuf {
+    state_dir().join(BACKUP_FILE_NAME)
+}
+
+/// Where the brightness of the LED `name` is stored while it is off
+pub fn led_backup_file(name: &str) -> PathBuf {
+    state_dir().join(format!("{}.{}", BACKUP_FILE_NAME, name))
+}
+
+/// Where versions up to 1.0.0 kept the backup. They never expanded the `~`,
+/// so the path is relative to the working directory
+pub fn legacy_backup_file() -> PathBuf {
+    Path::new("~/.local/share").join(BACKUP_FILE_NAME)
+}
+
+/// Replace `path` with `contents` so readers never see a partial write.
+/// Missing parent directories are created
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let error = |err| ScreenpadError::io(path, err);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(error)?;
+    }
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let written = fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|_| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(error(err));
+    }
+
+    Ok(())
+}
+
+/// Copy a backup left at `legacy` to `to`, unless `to` already exists.
+/// Only a brightness is taken, and `legacy` stays in place, the name is
+/// generic enough to belong to another tool
+pub fn migrate_legacy_backup(legacy: &Path, to: &Path) -> Result<()> {
+    if !legacy.is_file() || to.exists() {
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(legacy).map_err(|err| ScreenpadError::io(legacy, err))?;
+    let contents = contents.trim_end();
+    if contents.parse::<i16>().is_err() {
+        return Ok(());
+    }
+    write_atomic(to, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn write_atomic_creates_parents_and_replaces() {
+        let dir = TempDir::new();
+        let path = dir.path().join("state/screenpadctl/brightness_backup");
+
+        write_atomic(&path, "120").unwrap();
+        assert_eq!(dir.read("state/screenpadctl/brightness_backup"), "120");
+        write_atomic(&path, "80").unwrap();
+        assert_eq!(dir.read("state/screenpadctl/brightness_backup"), "80");
+
+        let files: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().collect();
+        assert_eq!(files.len(), 1, "temporary file left behind");
+    }
+
+    #[test]
+    fn write_atomic_fails_without_touching_the_target() {
+        let dir = TempDir::new();
+        let path = dir.write("blocked/brightness_backup", "120");
+        // a directory where the temporary file should go
+        fs::create_dir(dir.path().join(format!(
+            "blocked/brightness_backup.{}.tmp",
+            std::process::id()
+        )))
+        .unwrap();
+
+        assert!(write_atomic(&path, "80").is_err());
+        assert_eq!(dir.read("blocked/brightness_backup"), "120");
+    }
+
+    #[test]
+    fn state_dir_prefers_xdg_over_home() {
+        let state = PathBuf::from("/xdg/state");
+        let home = PathBuf::from("/home/me");
+
+        assert_eq!(
+            state_dir_in(Some(state.clone()), Some(home.clone())),
+            Path::new("/xdg/state/screenpadctl")
+        );
+        assert_eq!(
+            state_dir_in(None, Some(home)),
+            Path::new("/home/me/.local/state/screenpadctl")
+        );
+        assert_eq!(state_dir_in(None, None), env::temp_dir().join(APP_NAME));
+    }
+
+    #[test]
+    fn relative_xdg_paths_are_ignored() {
+        assert_eq!(absolute(Some("relative/state".into())), None);
+        assert_eq!(absolute(Some("".into())), None);
+        assert_eq!(
+            absolute(Some("/run/user/1000".into())),
+            Some(PathBuf::from("/run/user/1000"))
+        );
+    }
+
+    #[test]
+    fn runtime_dir_falls_back_to_the_state_dir() {
+        let state = PathBuf::from("/home/me/.local/state/screenpadctl");
+
+        assert_eq!(
+            runtime_dir_in(Some("/run/user/1000".into()), state.clone()),
+            Path::new("/run/user/1000/screenpadctl")
+        );
+        assert_eq!(runtime_dir_in(None, state.clone()), state);
+    }
+
+    #[test]
+    fn migrates_a_legacy_backup_and_keeps_it() {
+        let dir = TempDir::new();
+        let legacy = dir.write("~/.local/share/brightness_backup", "140\n");
+        let to = dir.path().join("state/brightness_backup");
+
+        migrate_legacy_backup(&legacy, &to).unwrap();
+        assert_eq!(dir.read("state/brightness_backup"), "140");
+        assert!(legacy.exists());
+    }
+
+    #[test]
+    fn never_migrates_over_a_backup() {
+        let dir = TempDir::new();
+        let legacy = dir.write("legacy", "140\n");
+        let to = dir.write("state/brightness_backup", "90");
+
+        migrate_legacy_backup(&legacy, &to).unwrap();
+        assert_eq!(dir.read("state/brightness_backup"), "90");
+    }
+
+    #[test]
+    fn leaves_foreign_files_alone() {
+        let dir = TempDir::new();
+        let to = dir.path().join("state/brightness_backup");
+
+        for contents in ["0.75", "", "bright", "99999"] {
+            let legacy = dir.write("legacy", contents);
+            migrate_legacy_backup(&legacy, &to).unwrap();
+            assert!(!to.exists(), "{:?} was migrated", contents);
+            assert_eq!(dir.read("legacy"), contents);
+        }
+        migrate_legacy_backup(&dir.path().join("missing"), &to).unwrap();
+        assert!(!to.exists());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::cli::StatusFormat;
+use screenpadctl::config::StatusConfig;
+use screenpadctl::Outcome;
+use serde_json::json;
+
+/// Render `outcome` for a status bar. `follow` asks for a single line per
+/// update, as bars reading a running command expect
+pub fn render(
+    format: StatusFormat,
+    outcome: &Outcome,
+    icons: &StatusConfig,
+    follow: bool,
+) -> String {
+    let icon = match outcome.state.as_str() {
+        "off" => &icons.icon_off,
+        "dim" => &icons.icon_dim,
+        _ => &icons.icon_on,
+    };
+    let percentage = outcome.percentage.round();
+    let text = format!("{} {}%", icon, percentage);
+
+    match format {
+        StatusFormat::Plain => format!(
+            "{} {}% ({}/{})",
+            outcome.state, percentage, outcome.brightness, outcome.max_brightness
+        ),
+        StatusFormat::Waybar => json!({
+            "text": text,
+            "alt": outcome.state,
+            "tooltip": format!(
+                "Screenpad {}, brightness {}/{}",
+                outcome.state, outcome.brightness, outcome.max_brightness
+            ),
+            "class": outcome.state,
+            "percentage": percentage as i64,
+        })
+        .to_string(),
+        StatusFormat::Polybar => text,
+        // full_text, then short_text, unless i3blocks reads a running command
+        StatusFormat::I3blocks if follow => text,
+        StatusFormat::I3blocks => format!("{}\n{}%", text, percentage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn outcome(state: &str, brightness: i16, percentage: f64) -> Outcome {
+        Outcome {
+            action: "status".to_string(),
+            changed: false,
+            message: String::new(),
+            brightness,
+            max_brightness: 255,
+            percentage,
+            state: state.to_string(),
+            backup: None,
+            interrupted: false,
+        }
+    }
+
+    fn render_default(format: StatusFormat, outcome: &Outcome, follow: bool) -> String {
+        render(format, outcome, &StatusConfig::default(), follow)
+    }
+
+    #[test]
+    fn waybar_gets_its_keys() {
+        let line = render_default(StatusFormat::Waybar, &outcome("on", 102, 40.0), false);
+        assert!(!line.contains('\n'));
+
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["text"], "☀ 40%");
+        assert_eq!(value["alt"], "on");
+        assert_eq!(value["class"], "on");
+        assert_eq!(value["percentage"], 40);
+        assert_eq!(value["tooltip"], "Screenpad on, brightness 102/255");
+    }
+
+    #[test]
+    fn icons_follow_the_state() {
+        let icons = StatusConfig {
+            icon_on: "ON".to_string(),
+            icon_dim: "DIM".to_string(),
+            icon_off: "OFF".to_string(),
+        };
+        for (state, icon) in [("on", "ON"), ("dim", "DIM"), ("off", "OFF")] {
+            let line = render(
+                StatusFormat::Polybar,
+                &outcome(state, 0, 0.0),
+                &icons,
+                false,
+            );
+            assert_eq!(line, format!("{} 0%", icon));
+        }
+
+        let line = render_default(StatusFormat::Polybar, &outcome("dim", 1, 0.4), false);
+        assert_eq!(line, "◐ 0%");
+        let line = render_default(StatusFormat::Polybar, &outcome("off", 0, 0.0), false);
+        assert_eq!(line, "○ 0%");
+    }
+
+    #[test]
+    fn polybar_gets_one_line() {
+        let line = render_default(StatusFormat::Polybar, &outcome("on", 255, 100.0), false);
+        assert_eq!(line, "☀ 100%");
+        let followed = render_default(StatusFormat::Polybar, &outcome("on", 255, 100.0), true);
+        assert_eq!(followed, line);
+    }
+
+    #[test]
+    fn i3blocks_gets_full_and_short_text() {
+        let line = render_default(StatusFormat::I3blocks, &outcome("on", 128, 50.2), false);
+        assert_eq!(line, "☀ 50%\n50%");
+    }
+
+    #[test]
+    fn i3blocks_gets_one_line_when_following() {
+        let line = render_default(StatusFormat::I3blocks, &outcome("on", 128, 50.2), true);
+        assert_eq!(line, "☀ 50%");
+    }
+
+    #[test]
+    fn plain_spells_out_the_state() {
+        let line = render_default(StatusFormat::Plain, &outcome("dim", 1, 0.4), false);
+        assert_eq!(line, "dim 0% (1/255)");
+    }
+}
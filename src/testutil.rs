@@ -0,0 +1,58 @@
+//! Helpers shared by the unit tests
+
+use crate::backend::MockBackend;
+use crate::screenpad::Screenpad;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directory removed again on drop, for fake sysfs trees and backups
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "screenpadctl-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).expect("create temp dir");
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `contents` to `name` below the directory, creating parents
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().expect("file in the temp dir")).expect("create dirs");
+        fs::write(&path, contents).expect("write file");
+        path
+    }
+
+    /// Trimmed contents of `name` below the directory
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path.join(name))
+            .expect("read file")
+            .trim_end()
+            .to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// In-memory screenpad at `brightness` of `max`, backing up into `dir`
+pub fn screenpad(dir: &TempDir, brightness: i16, max: i16) -> Screenpad {
+    Screenpad::new(Box::new(MockBackend::new(brightness, max)))
+        .with_backup_file(dir.path().join("brightness_backup"))
+}
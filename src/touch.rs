@@ -0,0 +1,162 @@
+use crate::backend::write_int;
+use crate::discovery::class_entries;
+use crate::error::Result;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Settings for the screenpad touchscreen
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct TouchConfig {
+    /// Names of the input devices to inhibit while the screen is off, as in
+    /// `/sys/class/input/*/name`. `*` matches anything, none disables
+    pub patterns: Vec<String>,
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no `*`, the whole name has to match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Input devices of the screenpad touchscreen under `<sysfs_root>/class/input`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TouchInput {
+    sysfs_root: PathBuf,
+    patterns: Vec<String>,
+}
+
+impl TouchInput {
+    pub fn new(sysfs_root: &Path, patterns: &[String]) -> Self {
+        Self {
+            sysfs_root: sysfs_root.to_path_buf(),
+            patterns: patterns.to_vec(),
+        }
+    }
+
+    pub fn set_patterns(&mut self, patterns: &[String]) {
+        self.patterns = patterns.to_vec();
+    }
+
+    /// Inhibitable devices with a matching name. Looked up on every use, as
+    /// input devices come back under new numbers after a resume
+    pub fn devices(&self) -> Vec<PathBuf> {
+        class_entries(&self.sysfs_root.join("class").join("input"))
+            .into_iter()
+            .filter(|dir| dir.join("inhibited").exists())
+            .filter(|dir| {
+                let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+                let name = name.trim_end();
+                self.patterns.iter().any(|pattern| matches(pattern, name))
+            })
+            .collect()
+    }
+
+    /// Stop or resume delivering touches. Returns the number of devices
+    pub fn set_inhibited(&self, inhibited: bool) -> Result<usize> {
+        let devices = self.devices();
+        for device in &devices {
+            write_int(&device.join("inhibited"), inhibited as i16)?;
+        }
+        Ok(devices.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn matches_without_a_star() {
+        assert!(matches("ELAN9009:00 04F3:2C1B", "ELAN9009:00 04F3:2C1B"));
+        assert!(!matches("ELAN9009:00", "ELAN9009:00 04F3:2C1B"));
+        assert!(!matches("ELAN9009:00 04F3:2C1B", "ELAN9009:00"));
+        assert!(matches("", ""));
+        assert!(!matches("", "ELAN"));
+    }
+
+    #[test]
+    fn matches_leading_and_trailing_stars() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("ELAN*", "ELAN9009:00 04F3:2C1B"));
+        assert!(!matches("ELAN*", "Synaptics ELAN"));
+        assert!(matches("*Touchscreen", "ELAN9009:00 Touchscreen"));
+        assert!(!matches("*Touchscreen", "Touchscreen Stylus"));
+        assert!(matches("*2C1B*", "ELAN9009:00 04F3:2C1B Touchscreen"));
+        assert!(matches("*2C1B*", "2C1B"));
+    }
+
+    #[test]
+    fn matches_repeated_segments() {
+        assert!(matches("*04F3*04F3*", "04F3:04F3"));
+        assert!(!matches("*04F3*04F3*", "ELAN 04F3"));
+        assert!(matches("a*a", "aa"));
+        assert!(matches("a*a", "aba"));
+        assert!(!matches("a*a", "a"));
+        assert!(matches("ab*b*b", "abbb"));
+        assert!(!matches("ab*b*b", "abb"));
+        assert!(matches("**", "x"));
+    }
+
+    fn input(dir: &TempDir, number: usize, name: &str, inhibitable: bool) {
+        let device = format!("class/input/input{}", number);
+        dir.write(&format!("{}/name", device), &format!("{}\n", name));
+        if inhibitable {
+            dir.write(&format!("{}/inhibited", device), "0\n");
+        }
+    }
+
+    #[test]
+    fn inhibits_matching_devices() {
+        let dir = TempDir::new();
+        input(&dir, 3, "AT Translated Set 2 keyboard", true);
+        input(&dir, 11, "ELAN9009:00 04F3:2C1B", true);
+        input(&dir, 12, "ELAN9009:00 04F3:2C1B Stylus", true);
+        input(&dir, 13, "ELAN9009:00 04F3:2C1B UNKNOWN", false);
+        let touch = TouchInput::new(dir.path(), &["ELAN9009:00*".to_string()]);
+
+        assert_eq!(touch.set_inhibited(true).unwrap(), 2);
+        assert_eq!(dir.read("class/input/input3/inhibited"), "0");
+        assert_eq!(dir.read("class/input/input11/inhibited"), "1");
+        assert_eq!(dir.read("class/input/input12/inhibited"), "1");
+
+        assert_eq!(touch.set_inhibited(false).unwrap(), 2);
+        assert_eq!(dir.read("class/input/input11/inhibited"), "0");
+        assert_eq!(dir.read("class/input/input12/inhibited"), "0");
+    }
+
+    #[test]
+    fn no_patterns_inhibit_nothing() {
+        let dir = TempDir::new();
+        input(&dir, 11, "ELAN9009:00 04F3:2C1B", true);
+
+        assert_eq!(
+            TouchInput::new(dir.path(), &[])
+                .set_inhibited(true)
+                .unwrap(),
+            0
+        );
+        assert_eq!(dir.read("class/input/input11/inhibited"), "0");
+        let missing = TouchInput::new(&dir.path().join("missing"), &["*".to_string()]);
+        assert_eq!(missing.set_inhibited(true).unwrap(), 0);
+    }
+}
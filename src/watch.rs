@@ -0,0 +1,165 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Waits for changes of a device. Writes from userspace show up through
+/// inotify on the device directory, changes by the kernel through sysfs poll
+/// on an attribute that supports it. Whatever neither catches is left to the
+/// caller comparing values after the timeout
+pub struct ChangeWatcher {
+    inotify: Option<OwnedFd>,
+    attribute: Option<PathBuf>,
+}
+
+impl ChangeWatcher {
+    /// Watch writes to files in `dir` and notifications of `attribute`
+    pub fn new(dir: Option<&Path>, attribute: Option<&Path>) -> Self {
+        Self {
+            inotify: dir.and_then(Self::inotify),
+            attribute: attribute.map(Path::to_path_buf),
+        }
+    }
+
+    fn inotify(dir: &Path) -> Option<OwnedFd> {
+        let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+
+        // SAFETY: plain syscall, the returned fd is owned from here on
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        // SAFETY: `fd` was just returned by inotify_init1 and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // whole writes only, a file caught between truncation and write reads empty
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO;
+        // SAFETY: `path` is a valid C string for the duration of the call
+        let watch = unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) };
+        (watch >= 0).then_some(fd)
+    }
+
+    /// Block until a change was signalled or `timeout` passes. Returns true
+    /// when a change was signalled
+    pub fn wait(&self, timeout: Duration) -> bool {
+        // sysfs only notifies after the attribute was read
+        let attribute = self.attribute.as_ref().and_then(|path| {
+            let mut file = File::open(path).ok()?;
+            file.read_to_string(&mut String::new()).ok()?;
+            Some(file)
+        });
+
+        let mut fds = Vec::new();
+        if let Some(inotify) = &self.inotify {
+            fds.push(libc::pollfd {
+                fd: inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            });
+        }
+        if let Some(file) = &attribute {
+            fds.push(libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLPRI | libc::POLLERR,
+                revents: 0,
+            });
+        }
+        if fds.is_empty() {
+            thread::sleep(timeout);
+            return false;
+        }
+
+        let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        // SAFETY: `fds` holds valid pollfds on files that outlive the call
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+        let signalled = fds
+            .iter()
+            .any(|fd| fd.revents & (libc::POLLIN | libc::POLLPRI) != 0);
+
+        if let Some(inotify) = &self.inotify {
+            drain(inotify);
+        }
+
+        if ready != 0 && !signalled {
+            // error or an fd that is always ready, do not spin on it
+            thread::sleep(timeout);
+        }
+        signalled
+    }
+}
+
+/// Drop queued inotify events, the caller reads the new value itself
+fn drain(inotify: &OwnedFd) {
+    let mut events = [0u8; 4096];
+    loop {
+        // SAFETY: reads at most `events.len()` bytes into `events`
+        let read = unsafe {
+            libc::read(
+                inotify.as_raw_fd(),
+                events.as_mut_ptr().cast(),
+                events.len(),
+            )
+        };
+        if read <= 0 {
+            return;
+        }
+    }
+}
+
+/// Block until the kernel signals a change of the sysfs attribute at `path`
+/// or `timeout` passes. Returns true when a change was signalled.
+///
+/// Attributes such as `actual_brightness` of a backlight are notified
+/// through sysfs poll. Plain files never are, so on those this is a sleep
+/// and callers compare values to spot changes
+pub fn wait_for_change(path: &Path, timeout: Duration) -> bool {
+    ChangeWatcher::new(None, Some(path)).wait(timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn write_to_the_directory_is_signalled() {
+        let dir = TempDir::new();
+        let brightness = dir.write("brightness", "100\n");
+        let watcher = ChangeWatcher::new(Some(dir.path()), Some(&brightness));
+
+        let started = Instant::now();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            fs::write(&brightness, "120\n").unwrap();
+        });
+        assert!(watcher.wait(Duration::from_secs(10)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn times_out_without_a_write() {
+        let dir = TempDir::new();
+        let brightness = dir.write("brightness", "100\n");
+        let watcher = ChangeWatcher::new(Some(dir.path()), Some(&brightness));
+
+        let started = Instant::now();
+        assert!(!watcher.wait(Duration::from_millis(100)));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn plain_attribute_is_never_signalled() {
+        let dir = TempDir::new();
+        let brightness = dir.write("brightness", "100\n");
+
+        assert!(!wait_for_change(&brightness, Duration::from_millis(50)));
+        assert!(!ChangeWatcher::new(None, None).wait(Duration::from_millis(10)));
+    }
+}